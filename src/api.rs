@@ -1,7 +1,7 @@
 use std::{str::FromStr as _, time::Duration};
 
 use anyhow::Result;
-use chrono::Datelike as _;
+use chrono::{DateTime, Datelike as _};
 use hyper::Uri;
 use oxidebot::{
     api::{
@@ -20,17 +20,25 @@ use oxidebot::{
 };
 use telegram_bot_api_rs::{
     available_methods::payload::{
-        ChatIdPayload, GetFilePayload, RestrictChatMemberPayload, SendMediaGroupPayload,
-        SendMessagePayload, SendVenuePayload,
+        ApproveChatJoinRequestPayload, ChatIdPayload, DeclineChatJoinRequestPayload,
+        GetFilePayload, RestrictChatMemberPayload, SendMessagePayload, SendVenuePayload,
+        SetChatTitlePayload,
     },
-    available_types::{Birthdate, ChatMember, ChatPermissions, InputMedia, ReactionType},
+    available_types::{Birthdate, ChatMember, ChatPermissions, Message, ReactionType},
     stickers::payload::SendStickerPayload,
     updateing_messages::payload::{
-        DeleteMessagePayload, EditMessageMediaPayload, EditMessageTextPayload,
+        DeleteMessagePayload, EditMessageReplyMarkupPayload, EditMessageTextPayload,
     },
 };
 
-use crate::{bot::TelegramBot, segment::process_message_segments, utils::split_id};
+use crate::{
+    bot::TelegramBot,
+    request::{
+        call_api_with_markup, edit_media, edited_message, send_media, send_video_note, MediaTarget,
+    },
+    segment::{parse_message, process_message_segments, set_caption, OutgoingMessage},
+    utils::{self, message_thread_id, parse_user, split_id, split_target, utf16_len},
+};
 
 impl CallApiTrait for TelegramBot {
     fn send_message<'life0, 'async_trait>(
@@ -48,105 +56,13 @@ impl CallApiTrait for TelegramBot {
         'life0: 'async_trait,
         Self: ::core::marker::Sync + 'async_trait,
     {
-        let bot = self.bot.clone();
         Box::pin(async move {
-            let (text_segments, mut media_segments, reply, entities, venues, stickers) =
-                process_message_segments(message);
-            let mut results: Vec<SendMessageResponse> = Vec::new();
-            let chat_id = match target {
-                SendMessageTarget::Group(id) => id,
-                SendMessageTarget::Private(id) => id,
+            let target = match target {
+                SendMessageTarget::Group(id) | SendMessageTarget::Private(id) => id,
             };
-            let text = text_segments.join("\n");
-            if media_segments.is_empty() {
-                let response = bot
-                    .send_message(&SendMessagePayload {
-                        chat_id: chat_id.clone(),
-                        text,
-                        entities: Some(entities),
-                        reply_parameters: reply.clone(),
-                        ..Default::default()
-                    })
-                    .await?;
-                results.push(SendMessageResponse {
-                    sent_message_id: format!("{}_{}", response.chat.id, response.message_id),
-                });
-            } else {
-                if !entities.is_empty() {
-                    let msg = bot
-                        .send_message(&SendMessagePayload {
-                            chat_id: chat_id.clone(),
-                            text,
-                            entities: Some(entities),
-                            reply_parameters: reply.clone(),
-                            ..Default::default()
-                        })
-                        .await?;
-                    results.push(SendMessageResponse {
-                        sent_message_id: format!("{}_{}", msg.chat.id, msg.message_id),
-                    });
-                } else {
-                    let last = media_segments.last_mut().unwrap();
-                    match last {
-                        InputMedia::Photo { caption, .. }
-                        | InputMedia::Video { caption, .. }
-                        | InputMedia::Animation { caption, .. }
-                        | InputMedia::Document { caption, .. }
-                        | InputMedia::Audio { caption, .. } => {
-                            *caption = Some(text_segments.join("\n"));
-                        }
-                    }
-                }
-                let msg = bot
-                    .send_media_group(SendMediaGroupPayload {
-                        chat_id: chat_id.clone(),
-                        reply_parameters: reply.clone(),
-                        media: media_segments,
-                        ..Default::default()
-                    })
-                    .await?;
-                results.append(
-                    &mut msg
-                        .into_iter()
-                        .map(|m| SendMessageResponse {
-                            sent_message_id: format!("{}_{}", m.chat.id, m.message_id),
-                        })
-                        .collect::<Vec<SendMessageResponse>>(),
-                )
-            }
-            if !venues.is_empty() {
-                for venue in venues {
-                    let response = bot
-                        .send_venue(&SendVenuePayload {
-                            chat_id: chat_id.clone(),
-                            latitude: venue.location.latitude,
-                            longitude: venue.location.longitude,
-                            title: venue.title,
-                            reply_parameters: reply.clone(),
-                            ..Default::default()
-                        })
-                        .await?;
-                    results.push(SendMessageResponse {
-                        sent_message_id: format!("{}_{}", response.chat.id, response.message_id),
-                    });
-                }
-            }
-            if !stickers.is_empty() {
-                for sticker in stickers {
-                    let response = bot
-                        .send_sticker(SendStickerPayload {
-                            chat_id: chat_id.clone(),
-                            sticker,
-                            reply_parameters: reply.clone(),
-                            ..Default::default()
-                        })
-                        .await?;
-                    results.push(SendMessageResponse {
-                        sent_message_id: format!("{}_{}", response.chat.id, response.message_id),
-                    });
-                }
-            }
-            Ok(results)
+            let business_connection_id = self.business_route(&message).await;
+            self.send_message_through(message, target, business_connection_id)
+                .await
         })
     }
 
@@ -172,14 +88,15 @@ impl CallApiTrait for TelegramBot {
         Self: ::core::marker::Sync + 'async_trait,
     {
         Box::pin(async move {
-            let (chat_id, message_id) = split_id(message_id)?;
+            let (chat_id, telegram_message_id) = split_id(message_id.clone())?;
             self.bot
                 .delete_message(&DeleteMessagePayload {
                     chat_id: chat_id,
-                    message_id: message_id.parse()?,
+                    message_id: telegram_message_id.parse()?,
                     ..Default::default()
                 })
                 .await?;
+            self.echo_deleted_message(message_id);
             Ok(())
         })
     }
@@ -208,41 +125,74 @@ impl CallApiTrait for TelegramBot {
     {
         Box::pin(async move {
             let (chat_id, message_id) = split_id(message_id)?;
+            let new_message = self.apply_segment_policy(&chat_id, new_message).await?;
+            let mention_labels = self.mention_labels(&chat_id, &new_message).await;
 
-            let (text_segments, mut media_segments, ..) = process_message_segments(new_message);
-            if media_segments.is_empty() {
-                self.bot
-                    .edit_message_text(&EditMessageTextPayload {
+            let OutgoingMessage {
+                text,
+                entities,
+                mut media,
+                uploads,
+                mut reply_markup,
+                video_notes,
+                ..
+            } = process_message_segments(new_message, &mention_labels)?;
+            if !video_notes.is_empty() {
+                tracing::warn!("Video notes can't be edited into a message, ignored");
+            }
+            if reply_markup
+                .as_ref()
+                .is_some_and(|markup| markup.get("inline_keyboard").is_none())
+            {
+                tracing::warn!("Only inline keyboards can be edited, reply markup ignored");
+                reply_markup = None;
+            }
+            let result = if media.is_empty() && text.is_empty() && reply_markup.is_some() {
+                call_api_with_markup::<serde_json::Value, _>(
+                    &self.bot,
+                    "editMessageReplyMarkup",
+                    &EditMessageReplyMarkupPayload {
                         chat_id: Some(chat_id),
                         message_id: Some(message_id.parse()?),
-                        text: text_segments.join("\n"),
                         ..Default::default()
-                    })
-                    .await?;
+                    },
+                    reply_markup.as_ref(),
+                )
+                .await?
+            } else if media.is_empty() {
+                call_api_with_markup::<serde_json::Value, _>(
+                    &self.bot,
+                    "editMessageText",
+                    &EditMessageTextPayload {
+                        chat_id: Some(chat_id),
+                        message_id: Some(message_id.parse()?),
+                        text,
+                        entities: Some(entities),
+                        ..Default::default()
+                    },
+                    reply_markup.as_ref(),
+                )
+                .await?
             } else {
-                if media_segments.len() > 1 {
+                if media.len() > 1 {
                     tracing::warn!("Media segments more than 1, only the first one will be sent");
                 }
-                match media_segments.last_mut() {
-                    Some(InputMedia::Photo { caption, .. })
-                    | Some(InputMedia::Video { caption, .. })
-                    | Some(InputMedia::Animation { caption, .. })
-                    | Some(InputMedia::Document { caption, .. })
-                    | Some(InputMedia::Audio { caption, .. }) => {
-                        *caption = Some(text_segments.join("\n"));
-                    }
-                    _ => {}
-                };
-                self.bot
-                    .edit_message_media(&EditMessageMediaPayload {
-                        chat_id: Some(chat_id),
-                        message_id: Some(message_id.parse()?),
-                        media: media_segments.into_iter().next().unwrap(),
-                        business_connection_id: None,
-                        inline_message_id: None,
-                        reply_markup: None,
-                    })
-                    .await?;
+                set_caption(&mut media[0], text, entities);
+                edit_media(
+                    &self.bot,
+                    chat_id,
+                    message_id.parse()?,
+                    media.into_iter().next().unwrap(),
+                    uploads,
+                    reply_markup.as_ref(),
+                )
+                .await?
+            };
+            // The edit went through either way
+            match edited_message(result) {
+                Ok(Some(message)) => self.message_store.insert(&message),
+                Ok(None) => {}
+                Err(e) => tracing::warn!("Error while parsing edited message: {:?}", e),
             }
             Ok(())
         })
@@ -261,7 +211,7 @@ impl CallApiTrait for TelegramBot {
     )]
     fn get_message_detail<'life0, 'async_trait>(
         &'life0 self,
-        _: String,
+        message_id: String,
     ) -> ::core::pin::Pin<
         Box<
             dyn ::core::future::Future<Output = Result<GetMessageDetailResponse>>
@@ -274,9 +224,16 @@ impl CallApiTrait for TelegramBot {
         Self: ::core::marker::Sync + 'async_trait,
     {
         Box::pin(async move {
-            return Err(anyhow::anyhow!(
-                "Telegram doesn't support get message by message_id"
-            ));
+            // Telegram can't fetch a message by id, only recorded ones are known
+            let message = self.message_store.get(&message_id).ok_or(anyhow::anyhow!(
+                "Message {} not found in message history",
+                message_id
+            ))?;
+            Ok(GetMessageDetailResponse {
+                sender: message.from.clone().map(parse_user),
+                time: DateTime::from_timestamp(message.date, 0),
+                message: parse_message(message, &self.user_cache).segments,
+            })
         })
     }
 
@@ -448,13 +405,23 @@ impl CallApiTrait for TelegramBot {
         'life0: 'async_trait,
         Self: ::core::marker::Sync + 'async_trait,
     {
-        let _ = r#type;
-        let _ = duration;
-        let _ = group_id;
         Box::pin(async move {
-            return Err(anyhow::anyhow!(
-                "Telegram doesn't support mute whole group."
-            ));
+            match r#type {
+                GroupMuteType::Mute => {
+                    self.mute_whole_group(group_id.clone()).await?;
+                    if let Some(duration) = duration {
+                        self.schedule_unmute(group_id, duration);
+                    }
+                }
+                GroupMuteType::Unmute => {
+                    if !self.unmute_whole_group(group_id).await? {
+                        return Err(anyhow::anyhow!(
+                            "No permissions snapshot found for group, it wasn't muted by this bot."
+                        ));
+                    }
+                }
+            }
+            Ok(())
         })
     }
 
@@ -565,13 +532,13 @@ impl CallApiTrait for TelegramBot {
         'life0: 'async_trait,
         Self: ::core::marker::Sync + 'async_trait,
     {
-        let _ = r#type;
-        let _ = user_id;
-        let _ = group_id;
         Box::pin(async move {
-            return Err(anyhow::anyhow!(
-                "Telegram doesn't support change group admin."
-            ));
+            match r#type {
+                GroupAdminChangeType::Set => {
+                    self.promote_group_member(group_id, user_id, None).await
+                }
+                GroupAdminChangeType::Unset => self.demote_group_member(group_id, user_id).await,
+            }
         })
     }
 
@@ -598,13 +565,9 @@ impl CallApiTrait for TelegramBot {
         'life0: 'async_trait,
         Self: ::core::marker::Sync + 'async_trait,
     {
-        let _ = new_alias;
-        let _ = user_id;
-        let _ = group_id;
         Box::pin(async move {
-            return Err(anyhow::anyhow!(
-                "Telegram doesn't support set group member alias."
-            ));
+            self.set_admin_custom_title(group_id, user_id, new_alias)
+                .await
         })
     }
 
@@ -678,12 +641,33 @@ impl CallApiTrait for TelegramBot {
         'life0: 'async_trait,
         Self: ::core::marker::Sync + 'async_trait,
     {
-        let _ = new_profile;
-        let _ = group_id;
         Box::pin(async move {
-            return Err(anyhow::anyhow!(
-                "Telegram doesn't support set group profile."
-            ));
+            let GroupProfile { name, avatar, .. } = new_profile;
+            if let Some(name) = name {
+                self.bot
+                    .set_chat_title(&SetChatTitlePayload {
+                        chat_id: group_id.clone(),
+                        title: name,
+                    })
+                    .await?;
+            }
+            if let Some(avatar) = avatar {
+                self.set_group_photo(
+                    group_id,
+                    File {
+                        name: avatar
+                            .path()
+                            .rsplit('/')
+                            .next()
+                            .unwrap_or_default()
+                            .to_string(),
+                        uri: Some(avatar),
+                        ..Default::default()
+                    },
+                )
+                .await?;
+            }
+            Ok(())
         })
     }
 
@@ -1114,12 +1098,27 @@ impl CallApiTrait for TelegramBot {
         'life0: 'async_trait,
         Self: ::core::marker::Sync + 'async_trait,
     {
-        let _ = response;
-        let _ = id;
         Box::pin(async move {
-            return Err(anyhow::anyhow!(
-                "Telegram doesn't support handle add group request."
-            ));
+            let (chat_id, user_id) = split_id(id)?;
+            match response {
+                RequestResponse::Approve => {
+                    self.bot
+                        .approve_chat_join_request(&ApproveChatJoinRequestPayload {
+                            chat_id,
+                            user_id: user_id.parse()?,
+                        })
+                        .await?;
+                }
+                RequestResponse::Reject => {
+                    self.bot
+                        .decline_chat_join_request(&DeclineChatJoinRequestPayload {
+                            chat_id,
+                            user_id: user_id.parse()?,
+                        })
+                        .await?;
+                }
+            }
+            Ok(())
         })
     }
 
@@ -1196,3 +1195,199 @@ impl CallApiTrait for TelegramBot {
         })
     }
 }
+
+impl TelegramBot {
+    /// `send_message`, on behalf of the business account of `business_connection_id` if given.
+    pub(crate) async fn send_message_through(
+        &self,
+        message: Vec<MessageSegment>,
+        target: String,
+        business_connection_id: Option<String>,
+    ) -> Result<Vec<SendMessageResponse>> {
+        let bot = self.bot.clone();
+        let (chat_id, thread_id) = split_target(target)?;
+        // Replies to a topic message stay in its topic. Group ids carry no thread, so a plain
+        // `Matcher::try_send_message` lands in General, reply or target `{chat_id}_{thread_id}`
+        let thread_id = thread_id.or_else(|| {
+            message.iter().find_map(|segment| match segment {
+                MessageSegment::Reply { message_id } => message_thread_id(message_id),
+                _ => None,
+            })
+        });
+        let message = self.apply_segment_policy(&chat_id, message).await?;
+        let mention_labels = self.mention_labels(&chat_id, &message).await;
+        let OutgoingMessage {
+            text,
+            entities,
+            mut media,
+            uploads,
+            reply,
+            venues,
+            stickers,
+            video_notes,
+            reply_markup,
+        } = process_message_segments(message, &mention_labels)?;
+        let mut results: Vec<SendMessageResponse> = Vec::new();
+        let only_main = video_notes.is_empty() && venues.is_empty() && stickers.is_empty();
+        // The reply markup goes on the last message sent
+        let markup_on_main = if only_main {
+            reply_markup.as_ref()
+        } else {
+            None
+        };
+        // Empty text is only sent when there is nothing else to send
+        if media.is_empty() && (!text.is_empty() || only_main) {
+            let response: Message = call_api_with_markup(
+                &bot,
+                "sendMessage",
+                &SendMessagePayload {
+                    business_connection_id: business_connection_id.clone(),
+                    chat_id: chat_id.clone(),
+                    message_thread_id: thread_id,
+                    text,
+                    entities: Some(entities),
+                    reply_parameters: reply.clone(),
+                    ..Default::default()
+                },
+                markup_on_main,
+            )
+            .await?;
+            self.message_store.insert(&response);
+            results.push(SendMessageResponse {
+                sent_message_id: utils::message_id(&response),
+            });
+        } else if !media.is_empty() {
+            // Telegram limits captions to 1024 characters
+            if utf16_len(&text) > 1024 {
+                let msg = bot
+                    .send_message(&SendMessagePayload {
+                        business_connection_id: business_connection_id.clone(),
+                        chat_id: chat_id.clone(),
+                        message_thread_id: thread_id,
+                        text,
+                        entities: Some(entities),
+                        reply_parameters: reply.clone(),
+                        ..Default::default()
+                    })
+                    .await?;
+                self.message_store.insert(&msg);
+                results.push(SendMessageResponse {
+                    sent_message_id: utils::message_id(&msg),
+                });
+            } else if !text.is_empty() {
+                set_caption(media.last_mut().unwrap(), text, entities);
+            }
+            let msg = send_media(
+                &bot,
+                MediaTarget {
+                    chat_id: chat_id.clone(),
+                    thread_id,
+                    business_connection_id: business_connection_id.clone(),
+                },
+                media,
+                uploads,
+                reply.clone(),
+                markup_on_main,
+            )
+            .await?;
+            results.append(
+                &mut msg
+                    .into_iter()
+                    .map(|m| {
+                        self.message_store.insert(&m);
+                        SendMessageResponse {
+                            sent_message_id: utils::message_id(&m),
+                        }
+                    })
+                    .collect::<Vec<SendMessageResponse>>(),
+            )
+        }
+        let video_note_count = video_notes.len();
+        for (i, (file, duration)) in video_notes.into_iter().enumerate() {
+            let markup = if venues.is_empty() && stickers.is_empty() && i + 1 == video_note_count {
+                reply_markup.as_ref()
+            } else {
+                None
+            };
+            let response = send_video_note(
+                &bot,
+                MediaTarget {
+                    chat_id: chat_id.clone(),
+                    thread_id,
+                    business_connection_id: business_connection_id.clone(),
+                },
+                &file,
+                duration,
+                reply.clone(),
+                markup,
+            )
+            .await?;
+            self.message_store.insert(&response);
+            results.push(SendMessageResponse {
+                sent_message_id: utils::message_id(&response),
+            });
+        }
+        let venue_count = venues.len();
+        for (i, venue) in venues.into_iter().enumerate() {
+            let markup = if stickers.is_empty() && i + 1 == venue_count {
+                reply_markup.as_ref()
+            } else {
+                None
+            };
+            let response: Message = call_api_with_markup(
+                &bot,
+                "sendVenue",
+                &SendVenuePayload {
+                    business_connection_id: business_connection_id.clone(),
+                    chat_id: chat_id.clone(),
+                    message_thread_id: thread_id,
+                    latitude: venue.location.latitude,
+                    longitude: venue.location.longitude,
+                    title: venue.title,
+                    reply_parameters: reply.clone(),
+                    ..Default::default()
+                },
+                markup,
+            )
+            .await?;
+            self.message_store.insert(&response);
+            results.push(SendMessageResponse {
+                sent_message_id: utils::message_id(&response),
+            });
+        }
+        let sticker_count = stickers.len();
+        for (i, sticker) in stickers.into_iter().enumerate() {
+            let markup = if i + 1 == sticker_count {
+                reply_markup.as_ref()
+            } else {
+                None
+            };
+            let response: Message = call_api_with_markup(
+                &bot,
+                "sendSticker",
+                &SendStickerPayload {
+                    business_connection_id: business_connection_id.clone(),
+                    chat_id: chat_id.clone(),
+                    message_thread_id: thread_id,
+                    sticker,
+                    reply_parameters: reply.clone(),
+                    ..Default::default()
+                },
+                markup,
+            )
+            .await?;
+            self.message_store.insert(&response);
+            results.push(SendMessageResponse {
+                sent_message_id: utils::message_id(&response),
+            });
+        }
+        // Replies to these go out through the same connection
+        if let Some(connection_id) = business_connection_id {
+            for result in &results {
+                self.business_registry
+                    .record_message(&result.sent_message_id, connection_id.clone());
+            }
+        }
+        Ok(results)
+    }
+}
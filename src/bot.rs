@@ -1,22 +1,142 @@
-use std::{any::Any, sync::Arc};
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
 
-use oxidebot::{bot::BotObject, matcher::Matcher, source::bot::BotInfo, BotTrait};
-use telegram_bot_api_rs::getting_updates::GetUpdateConfig;
-use tokio::sync::broadcast;
+use oxidebot::{
+    bot::BotObject,
+    matcher::Matcher,
+    source::{
+        bot::BotInfo,
+        group::Group,
+        user::{User, UserProfile},
+    },
+    BotTrait,
+};
+use telegram_bot_api_rs::{
+    available_types::ChatAdministratorRights,
+    getting_updates::{types::UpdateData, GetUpdateConfig},
+};
+use tokio::{
+    sync::{broadcast, watch, Mutex},
+    task::AbortHandle,
+};
 
-use crate::{event::UpdateEvent, SERVER};
+use crate::{
+    command::CommandList,
+    event::{message_deleted_event, BotDeletionEvent, UpdateEvent},
+    segment::{MentionLabel, SegmentPolicy},
+    store::{
+        BusinessRegistry, InlineQueryRegistry, MessageBackend, MessageStore, PermissionStore,
+        PollRegistry, UserCache,
+    },
+    utils::{self, parse_group},
+    webhook::WebhookConfig,
+    SERVER,
+};
 
 #[derive(Debug, Clone)]
 pub struct TelegramBot {
     pub bot: Arc<telegram_bot_api_rs::bot::Bot>,
     pub bot_info: Arc<BotInfo>,
     pub config: GetUpdateConfig,
+    pub options: Arc<TelegramBotOptions>,
+    pub permission_store: PermissionStore,
+    pub user_cache: UserCache,
+    pub poll_registry: PollRegistry,
+    pub inline_queries: InlineQueryRegistry,
+    pub business_registry: BusinessRegistry,
+    pub message_store: MessageStore,
+    pub(crate) webhook_shutdown: Arc<watch::Sender<bool>>,
+    pub(crate) pending_callbacks: Arc<Mutex<HashSet<String>>>,
+    /// The pending timed unmute of each group muted by `mute_group` with a duration.
+    pub(crate) unmute_timers: Arc<std::sync::Mutex<HashMap<String, (u64, AbortHandle)>>>,
+    /// Where matchers go once `start_sending_events` is called, for events the bot causes itself.
+    pub(crate) event_sender: Arc<OnceLock<broadcast::Sender<Matcher>>>,
+}
+
+/// Telegram specific behaviour that can't be expressed through the oxidebot api.
+#[derive(Debug, Clone)]
+pub struct TelegramBotOptions {
+    /// Rights granted by `change_group_admin` when promoting a member.
+    /// Can be overridden per call with `TelegramBot::promote_group_member`.
+    pub admin_rights: ChatAdministratorRights,
+    /// Where the group permissions snapshotted by `mute_group` are persisted.
+    /// Snapshots only live in memory if `None`.
+    /// Construction fails if the file exists but can't be read, rather than overwriting it.
+    pub permission_store_path: Option<PathBuf>,
+    /// Whether `set_group_member_alias` promotes a non-admin member with minimal rights,
+    /// since Telegram only shows custom titles of administrators.
+    pub promote_for_alias: bool,
+    /// Receive updates through a webhook instead of long polling.
+    /// Call `TelegramBot::stop_webhook` on shutdown to delete it.
+    pub webhook: Option<WebhookConfig>,
+    /// What to do with outgoing segments telegram can't represent.
+    pub segment_policy: SegmentPolicy,
+    /// Visible text of outgoing mentions.
+    pub mention_label: MentionLabel,
+    /// Command lists registered with `setMyCommands` on startup.
+    pub commands: Vec<CommandList>,
+    /// Answer callback queries left unanswered by handlers after this long,
+    /// so that clients stop showing a spinner.
+    pub callback_auto_answer: Option<Duration>,
+    /// How many received and sent messages are kept in memory, so that `get_message_detail`
+    /// and the `old_message` of edit events can be answered. None are kept if `0`.
+    pub message_history: usize,
+    /// Where message history is persisted, on top of the in-memory cache.
+    pub message_backend: Option<Arc<dyn MessageBackend>>,
+    /// Emit a `MessageDeletedEvent` when the bot deletes a message through `delete_message`,
+    /// since Telegram doesn't notify about it.
+    pub echo_deleted_messages: bool,
+}
+
+impl Default for TelegramBotOptions {
+    fn default() -> Self {
+        Self {
+            admin_rights: ChatAdministratorRights {
+                is_anonymous: Some(false),
+                can_manage_chat: Some(true),
+                can_delete_messages: Some(true),
+                can_manage_video_chats: Some(true),
+                can_restrict_members: Some(true),
+                can_promote_members: Some(false),
+                can_change_info: Some(false),
+                can_invite_users: Some(true),
+                can_pin_messages: Some(true),
+                can_manage_topics: Some(true),
+                ..Default::default()
+            },
+            permission_store_path: None,
+            promote_for_alias: false,
+            webhook: None,
+            segment_policy: SegmentPolicy::default(),
+            mention_label: MentionLabel::default(),
+            commands: Vec::new(),
+            callback_auto_answer: None,
+            message_history: 0,
+            message_backend: None,
+            echo_deleted_messages: false,
+        }
+    }
 }
 
 impl TelegramBot {
     pub async fn new(token: String, config: GetUpdateConfig) -> BotObject {
+        Self::with_options(token, config, Default::default()).await
+    }
+
+    pub async fn with_options(
+        token: String,
+        config: GetUpdateConfig,
+        options: TelegramBotOptions,
+    ) -> BotObject {
         let bot = telegram_bot_api_rs::bot::Bot::new(token);
         let bot_info = bot.get_me().await.unwrap();
+        let user_cache = UserCache::default();
+        user_cache.insert(&bot_info);
         let bot_info = BotInfo {
             id: Some(bot_info.id.to_string()),
             nickname: Some(bot_info.username.unwrap_or_else(|| {
@@ -28,11 +148,31 @@ impl TelegramBot {
             })),
         };
         tracing::info!("Connection succeed: {:?}", bot_info);
-        Box::new(Self {
+        let permission_store = PermissionStore::load(options.permission_store_path.clone())
+            .await
+            .expect("Failed to load the permission store");
+        let message_store =
+            MessageStore::new(options.message_history, options.message_backend.clone());
+        let bot = Self {
             bot: bot.into(),
             bot_info: bot_info.into(),
             config: config,
-        })
+            options: options.into(),
+            permission_store,
+            user_cache,
+            poll_registry: PollRegistry::default(),
+            inline_queries: InlineQueryRegistry::default(),
+            business_registry: BusinessRegistry::default(),
+            message_store,
+            webhook_shutdown: Arc::new(watch::channel(false).0),
+            pending_callbacks: Default::default(),
+            unmute_timers: Default::default(),
+            event_sender: Default::default(),
+        };
+        if let Err(e) = bot.sync_commands().await {
+            tracing::error!("Error while registering commands: {:?}", e);
+        }
+        Box::new(bot)
     }
 }
 
@@ -63,13 +203,75 @@ impl BotTrait for TelegramBot {
         'life0: 'async_trait,
         Self: 'async_trait,
     {
-        self.bot.start_get_updates(self.config.clone());
         let mut subscriber = self.bot.subscribe_updates();
+        let _ = self.event_sender.set(sender.clone());
+        match self.options.webhook.clone() {
+            Some(webhook) => {
+                let bot = self.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = bot.run_webhook(&webhook).await {
+                        tracing::error!("Error while running webhook: {:?}", e);
+                    }
+                });
+            }
+            None => self.start_polling(),
+        }
         Box::pin(async move {
             loop {
                 match subscriber.recv().await {
                     Ok(update) => {
-                        let matchers = Matcher::new(UpdateEvent::new(update), self.clone_box());
+                        match &update {
+                            UpdateData::CallbackQuery { callback_query } => {
+                                self.track_callback_query(callback_query.id.clone()).await;
+                            }
+                            UpdateData::Poll { poll } => self.poll_registry.update(poll),
+                            UpdateData::BusinessConnection {
+                                business_connection,
+                            } => self
+                                .business_registry
+                                .update_connection(business_connection),
+                            UpdateData::BusinessMessage {
+                                business_message: m,
+                            }
+                            | UpdateData::EditedBusinessMessage {
+                                edited_business_message: m,
+                            } => {
+                                if let Some(connection_id) = &m.business_connection_id {
+                                    self.business_registry.record_message(
+                                        &utils::message_id(m),
+                                        connection_id.clone(),
+                                    );
+                                }
+                            }
+                            _ => {}
+                        }
+                        // Recorded once parsed, so that edits are compared with the old version
+                        let message = match &update {
+                            UpdateData::Message { message: m }
+                            | UpdateData::EditedMessage { edited_message: m }
+                            | UpdateData::ChannelPost { channel_post: m }
+                            | UpdateData::EditedChannelPost {
+                                edited_channel_post: m,
+                            }
+                            | UpdateData::BusinessMessage {
+                                business_message: m,
+                            }
+                            | UpdateData::EditedBusinessMessage {
+                                edited_business_message: m,
+                            } if self.message_store.is_enabled() => Some(m.clone()),
+                            _ => None,
+                        };
+                        let matchers = Matcher::new(
+                            UpdateEvent::new(
+                                update,
+                                self.user_cache.clone(),
+                                self.message_store.clone(),
+                            ),
+                            self.clone_box(),
+                        );
+                        if let Some(message) = message {
+                            self.message_store.insert(&message);
+                        }
                         for matcher in matchers {
                             sender.send(matcher).unwrap();
                         }
@@ -94,3 +296,57 @@ impl BotTrait for TelegramBot {
         self
     }
 }
+
+impl TelegramBot {
+    /// Emit a `BotDeletionEvent` for a message the bot deleted, if enabled.
+    pub(crate) fn echo_deleted_message(&self, message_id: String) {
+        if !self.options.echo_deleted_messages {
+            return;
+        }
+        let Some(sender) = self.event_sender.get() else {
+            return;
+        };
+        let group = match self.message_store.get(&message_id) {
+            Some(message) if message.chat.r#type == "private" => None,
+            Some(message) => Some(parse_group(message.chat)),
+            // Group and channel ids are negative
+            None => utils::split_id(message_id.clone())
+                .ok()
+                .filter(|(chat_id, _)| chat_id.starts_with('-'))
+                .map(|(chat_id, _)| Group {
+                    id: chat_id,
+                    profile: None,
+                }),
+        };
+        // The id of a private chat is that of the other user
+        let chat_user = match &group {
+            Some(_) => None,
+            None => utils::split_id(message_id.clone())
+                .ok()
+                .map(|(chat_id, _)| User {
+                    id: chat_id,
+                    profile: None,
+                    group_info: None,
+                }),
+        };
+        let operator = User {
+            id: self.bot_info.id.clone().unwrap_or_default(),
+            profile: Some(UserProfile {
+                nickname: self.bot_info.nickname.clone(),
+                ..Default::default()
+            }),
+            group_info: None,
+        };
+        let event = message_deleted_event(
+            message_id,
+            group,
+            chat_user,
+            Some(operator),
+            &self.message_store,
+            &self.user_cache,
+        );
+        for matcher in Matcher::new(Box::new(BotDeletionEvent(event)), self.clone_box()) {
+            let _ = sender.send(matcher);
+        }
+    }
+}
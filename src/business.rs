@@ -0,0 +1,58 @@
+use anyhow::Result;
+use oxidebot::{api::SendMessageResponse, source::message::MessageSegment};
+use telegram_bot_api_rs::{
+    available_methods::payload::GetBusinessConnectionPayload, available_types::BusinessConnection,
+};
+
+use crate::bot::TelegramBot;
+
+impl TelegramBot {
+    /// A business connection of the bot, fetched from Telegram if not seen since startup.
+    pub async fn business_connection(&self, connection_id: &str) -> Result<BusinessConnection> {
+        if let Some(connection) = self.business_registry.connection(connection_id) {
+            return Ok(connection);
+        }
+        let connection = self
+            .bot
+            .get_business_connection(&GetBusinessConnectionPayload {
+                business_connection_id: connection_id.to_string(),
+            })
+            .await?;
+        self.business_registry.update_connection(&connection);
+        Ok(connection)
+    }
+
+    /// The connection to send `message` through, if it replies to a business message
+    /// received or sent through a connection the bot can reply on behalf of.
+    pub(crate) async fn business_route(&self, message: &[MessageSegment]) -> Option<String> {
+        let connection_id = message.iter().find_map(|segment| match segment {
+            MessageSegment::Reply { message_id } => {
+                self.business_registry.message_connection(message_id)
+            }
+            _ => None,
+        })?;
+        match self.business_connection(&connection_id).await {
+            Ok(connection) if connection.is_enabled && connection.can_reply => Some(connection_id),
+            Ok(_) => {
+                tracing::warn!("Business connection can't reply, sending as the bot");
+                None
+            }
+            Err(e) => {
+                tracing::error!("Error while getting business connection: {:?}", e);
+                None
+            }
+        }
+    }
+
+    /// Send a message on behalf of the business account of `connection_id`,
+    /// to `chat_id`, a chat of the business account.
+    pub async fn send_business_message(
+        &self,
+        connection_id: String,
+        chat_id: String,
+        message: Vec<MessageSegment>,
+    ) -> Result<Vec<SendMessageResponse>> {
+        self.send_message_through(message, chat_id, Some(connection_id))
+            .await
+    }
+}
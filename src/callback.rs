@@ -0,0 +1,146 @@
+use anyhow::Result;
+use oxidebot::{event::any::AnyEventDataTrait, source::user::User};
+use telegram_bot_api_rs::{
+    available_methods::payload::AnswerCallbackQueryPayload,
+    available_types::{CallbackQuery, MaybeInaccessibleMessage},
+};
+
+use crate::{
+    bot::TelegramBot,
+    utils::{self, parse_user},
+};
+
+/// A button of an inline keyboard was pressed, sent as a `TelegramEventKind::CallbackQuery` event.
+/// Answer it with `TelegramBot::answer_callback_query`, or the client keeps showing a spinner.
+#[derive(Debug, Clone)]
+pub struct CallbackQueryEvent {
+    pub id: String,
+    pub sender: User,
+    /// The message holding the keyboard, as `{chat_id}_{message_id}`.
+    /// `None` for messages sent in inline mode, see `inline_message_id`.
+    pub message_id: Option<String>,
+    pub inline_message_id: Option<String>,
+    pub chat_instance: String,
+    /// `callback_data` of the pressed button.
+    pub data: Option<String>,
+    pub game_short_name: Option<String>,
+    pub raw: CallbackQuery,
+}
+
+impl From<CallbackQuery> for CallbackQueryEvent {
+    fn from(query: CallbackQuery) -> Self {
+        let message_id = match &query.message {
+            _ if !has_message(&query) => None,
+            MaybeInaccessibleMessage::Message(message) => Some(utils::message_id(message)),
+            MaybeInaccessibleMessage::InaccessibleMessage(message) => {
+                Some(format!("{}_{}", message.chat.id, message.message_id))
+            }
+        };
+        let non_empty = |s: &String| (!s.is_empty()).then(|| s.clone());
+        Self {
+            id: query.id.clone(),
+            sender: parse_user(query.from.clone()),
+            message_id,
+            inline_message_id: non_empty(&query.inline_message_id),
+            chat_instance: query.chat_instance.clone(),
+            data: non_empty(&query.data),
+            game_short_name: non_empty(&query.game_short_name),
+            raw: query,
+        }
+    }
+}
+
+/// Whether Telegram sent the `message` of a callback query. It sends either `message` or
+/// `inline_message_id`, and `parse_raw_update` fills the missing one with a placeholder.
+pub(crate) fn has_message(query: &CallbackQuery) -> bool {
+    query.inline_message_id.is_empty()
+}
+
+impl AnyEventDataTrait for CallbackQueryEvent {
+    fn clone_box(&self) -> Box<dyn AnyEventDataTrait> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+impl TelegramBot {
+    /// Answer a callback query, showing `text` as a toast (or an alert if `show_alert`),
+    /// or opening `url`.
+    pub async fn answer_callback_query(
+        &self,
+        query_id: String,
+        text: Option<String>,
+        show_alert: bool,
+        url: Option<String>,
+    ) -> Result<()> {
+        self.pending_callbacks.lock().await.remove(&query_id);
+        self.bot
+            .answer_callback_query(&AnswerCallbackQueryPayload {
+                callback_query_id: query_id,
+                text,
+                show_alert: Some(show_alert),
+                url,
+                cache_time: None,
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Answer the query with nothing after `TelegramBotOptions::callback_auto_answer`
+    /// unless it was answered before.
+    pub(crate) async fn track_callback_query(&self, query_id: String) {
+        let Some(timeout) = self.options.callback_auto_answer else {
+            return;
+        };
+        self.pending_callbacks.lock().await.insert(query_id.clone());
+        let bot = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+            if !bot.pending_callbacks.lock().await.remove(&query_id) {
+                return;
+            }
+            if let Err(e) = bot.answer_callback_query(query_id, None, false, None).await {
+                tracing::error!("Error while auto answering callback query: {:?}", e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use telegram_bot_api_rs::getting_updates::types::UpdateData;
+
+    use super::*;
+    use crate::event::parse_raw_update;
+
+    fn callback_query(query: serde_json::Value) -> CallbackQueryEvent {
+        let mut query = query;
+        query["id"] = "1".into();
+        query["from"] = json!({ "id": 7, "is_bot": false, "first_name": "a" });
+        query["chat_instance"] = "c".into();
+        let update = parse_raw_update(json!({ "update_id": 1, "callback_query": query })).unwrap();
+        match update.data {
+            UpdateData::CallbackQuery { callback_query } => callback_query.into(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn inline_message_has_no_message_id() {
+        let query = callback_query(json!({ "inline_message_id": "abc", "data": "x" }));
+        assert_eq!(query.message_id, None);
+        assert_eq!(query.inline_message_id.as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn inaccessible_message_keeps_its_id() {
+        let query = callback_query(json!({
+            "message": { "chat": { "id": 0, "type": "private" }, "message_id": 5, "date": 0 },
+        }));
+        assert_eq!(query.message_id.as_deref(), Some("0_5"));
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::Result;
+use oxidebot::matcher::Matcher;
+use telegram_bot_api_rs::{
+    available_methods::payload::SetMyCommandsPayload,
+    available_types::{BotCommand, BotCommandScope, Message, MessageEntity},
+    getting_updates::types::UpdateData,
+};
+
+use crate::{bot::TelegramBot, event::UpdateEvent};
+
+/// A bot command leading a message, `/name@botname args` with the `@botname` stripped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TelegramCommand {
+    pub name: String,
+    pub args: String,
+}
+
+impl TelegramCommand {
+    /// Arguments split on whitespace.
+    pub fn args(&self) -> Vec<&str> {
+        self.args.split_whitespace().collect()
+    }
+}
+
+/// Commands registered with `setMyCommands` for one scope and language.
+#[derive(Debug, Clone, Default)]
+pub struct CommandList {
+    /// `None` for the default scope.
+    pub scope: Option<BotCommandScope>,
+    /// `None` for users whose language has no dedicated list.
+    pub language_code: Option<String>,
+    pub commands: Vec<BotCommand>,
+}
+
+impl UpdateEvent {
+    /// The command leading the message of this update.
+    /// `None` if there is none or it's addressed to a bot other than `bot_username`.
+    pub fn command(&self, bot_username: &str) -> Option<TelegramCommand> {
+        match &self.0 {
+            UpdateData::Message { message } => message_command(message, bot_username),
+            UpdateData::ChannelPost { channel_post } => message_command(channel_post, bot_username),
+            _ => None,
+        }
+    }
+}
+
+impl TelegramBot {
+    /// The command addressed to this bot in the update of `matcher`.
+    pub fn command(&self, matcher: &Matcher) -> Option<TelegramCommand> {
+        let event = UpdateEvent::from_matcher(matcher)?;
+        event.command(self.bot_info.nickname.as_deref().unwrap_or_default())
+    }
+
+    /// Register `TelegramBotOptions::commands` with `setMyCommands`.
+    pub async fn sync_commands(&self) -> Result<()> {
+        for list in &self.options.commands {
+            self.bot
+                .set_my_commands(&SetMyCommandsPayload {
+                    commands: list.commands.clone(),
+                    scope: list.scope.clone(),
+                    language_code: list.language_code.clone(),
+                })
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+fn message_command(message: &Message, bot_username: &str) -> Option<TelegramCommand> {
+    match (&message.text, &message.entities) {
+        (Some(text), Some(entities)) => parse_command(text, entities, bot_username),
+        _ => match (&message.caption, &message.caption_entities) {
+            (Some(caption), Some(entities)) => parse_command(caption, entities, bot_username),
+            _ => None,
+        },
+    }
+}
+
+/// Parse a `BotCommand` entity at the start of `text`.
+pub fn parse_command(
+    text: &str,
+    entities: &[MessageEntity],
+    bot_username: &str,
+) -> Option<TelegramCommand> {
+    let length = entities.iter().find_map(|entity| match entity {
+        MessageEntity::BotCommand { offset: 0, length } => Some(*length as usize),
+        _ => None,
+    })?;
+    let units = text.encode_utf16().collect::<Vec<u16>>();
+    let command = String::from_utf16_lossy(units.get(..length)?);
+    let args = String::from_utf16_lossy(&units[length..]);
+    let command = command.trim_start_matches('/');
+    let name = match command.split_once('@') {
+        Some((name, username)) if username.eq_ignore_ascii_case(bot_username) => name,
+        Some(_) => return None,
+        None => command,
+    };
+    Some(TelegramCommand {
+        name: name.to_string(),
+        args: args.trim().to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(text: &str, offset: i64, length: i64) -> Option<TelegramCommand> {
+        parse_command(
+            text,
+            &[MessageEntity::BotCommand { offset, length }],
+            "OurBot",
+        )
+    }
+
+    #[test]
+    fn commands_addressed_to_us_are_parsed() {
+        assert_eq!(
+            command("/cmd@ourbot a b", 0, 11),
+            Some(TelegramCommand {
+                name: "cmd".to_string(),
+                args: "a b".to_string(),
+            })
+        );
+        assert_eq!(
+            command("/cmd", 0, 4),
+            Some(TelegramCommand {
+                name: "cmd".to_string(),
+                args: String::new(),
+            })
+        );
+    }
+
+    #[test]
+    fn commands_addressed_to_other_bots_are_ignored() {
+        assert_eq!(command("/cmd@OtherBot a", 0, 13), None);
+    }
+
+    #[test]
+    fn args_are_split_on_utf16_offsets() {
+        assert_eq!(
+            command("/say 😀 hi", 0, 4).unwrap().args(),
+            vec!["😀", "hi"]
+        );
+        assert_eq!(
+            command("/say😀 hi", 0, 4).unwrap(),
+            TelegramCommand {
+                name: "say".to_string(),
+                args: "😀 hi".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn commands_not_leading_the_text_are_ignored() {
+        assert_eq!(command("hi /cmd", 3, 4), None);
+    }
+}
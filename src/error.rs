@@ -0,0 +1,28 @@
+use std::fmt::Display;
+
+/// Errors specific to this crate, returned inside `anyhow::Error`.
+/// Use `err.downcast_ref::<TelegramError>()` to match on them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TelegramError {
+    /// The user must be an administrator of the group for this operation.
+    NotAdministrator { group_id: String, user_id: String },
+    /// `SegmentPolicy::Strict` refused to send segments telegram can't represent.
+    UnsupportedSegments { segments: Vec<String> },
+}
+
+impl Display for TelegramError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TelegramError::NotAdministrator { group_id, user_id } => write!(
+                f,
+                "User {} is not an administrator of group {}",
+                user_id, group_id
+            ),
+            TelegramError::UnsupportedSegments { segments } => {
+                write!(f, "Unsupported segments: {}", segments.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for TelegramError {}
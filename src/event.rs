@@ -6,41 +6,194 @@ use oxidebot::{
         any::{AnyEvent, AnyEventDataTrait},
         notice::{
             GroupAdminChangeEvent, GroupAdminChangeType, GroupMemberDecreaseEvent,
-            GroupMemberMuteChangeEvent, MessageEditedEvent, MessageReactionsEvent,
+            GroupMemberMuteChangeEvent, MessageDeletedEvent, MessageEditedEvent,
+            MessageReactionsEvent,
         },
         request::GroupAddEvent,
         Event, EventObject, MessageEvent,
     },
-    source::message::Message,
+    matcher::Matcher,
+    source::{group::Group, message::Message},
     EventTrait,
 };
 use telegram_bot_api_rs::{
     available_types::{
-        BusinessConnection, BusinessMessagesDeleted, ChatBoostRemoved, ChatBoostUpdated,
-        ChatMember, MessageReactionCountUpdated,
+        BusinessConnection, BusinessMessagesDeleted, Chat, ChatBoostRemoved, ChatBoostUpdated,
+        ChatMember, MaybeInaccessibleMessage, MessageReactionCountUpdated, Poll, PollAnswer, User,
     },
-    getting_updates::types::UpdateData,
-    inline_mode::types::{ChosenInlineResult, InlineQuery},
+    getting_updates::types::{Update, UpdateData},
     payments::types::{PreCheckoutQuery, ShippingQuery},
 };
 
 use crate::{
+    callback::{has_message, CallbackQueryEvent},
+    inline::{ChosenInlineResultEvent, InlineQueryEvent},
+    payment::{RefundedPaymentEvent, SuccessfulPaymentEvent},
     segment::{self, parse_message, parse_reaction},
-    utils::{parse_group, parse_user},
+    store::{MessageStore, UserCache},
+    utils::{self, parse_group, parse_private_chat, parse_user},
     SERVER,
 };
 
-pub struct UpdateEvent(pub UpdateData);
+/// Parse a raw update, as received by `start_polling` or the webhook.
+/// telegram_bot_api_rs types some optional fields of callback queries as required, so queries
+/// from inline messages (no `message`) or without `data` wouldn't parse at all.
+/// The missing fields are filled with empty values first so that such updates still parse.
+pub fn parse_raw_update(mut update: serde_json::Value) -> serde_json::Result<Update> {
+    if let Some(callback_query) = update
+        .get_mut("callback_query")
+        .and_then(|c| c.as_object_mut())
+    {
+        for field in ["inline_message_id", "data", "game_short_name", "chat_instance"] {
+            callback_query
+                .entry(field)
+                .or_insert_with(|| serde_json::Value::String(String::new()));
+        }
+        // Placeholder for inline messages, told apart by their `inline_message_id`
+        callback_query.entry("message").or_insert_with(|| {
+            serde_json::json!({ "chat": { "id": 0, "type": "" }, "message_id": 0, "date": 0 })
+        });
+    }
+    serde_json::from_value(update)
+}
+
+/// An update, with the cache `@username` mentions are resolved through
+/// and the history the previous version of edited messages is looked up in.
+pub struct UpdateEvent(pub UpdateData, pub UserCache, pub MessageStore);
 
 impl UpdateEvent {
-    pub fn new(update: UpdateData) -> EventObject {
-        Box::new(UpdateEvent(update))
+    pub fn new(update: UpdateData, users: UserCache, messages: MessageStore) -> EventObject {
+        Box::new(UpdateEvent(update, users, messages))
+    }
+
+    /// The update a matcher was built from, `None` for other servers.
+    pub fn from_matcher(matcher: &Matcher) -> Option<&UpdateEvent> {
+        matcher.event_object.as_any().downcast_ref()
+    }
+
+    /// The message of a (edited) message, channel post or business message,
+    /// or the message holding the keyboard of a callback query.
+    pub fn message(&self) -> Option<&telegram_bot_api_rs::available_types::Message> {
+        match &self.0 {
+            UpdateData::Message { message: m }
+            | UpdateData::EditedMessage { edited_message: m }
+            | UpdateData::ChannelPost { channel_post: m }
+            | UpdateData::EditedChannelPost {
+                edited_channel_post: m,
+            }
+            | UpdateData::BusinessMessage {
+                business_message: m,
+            }
+            | UpdateData::EditedBusinessMessage {
+                edited_business_message: m,
+            } => Some(m),
+            UpdateData::CallbackQuery { callback_query } if has_message(callback_query) => {
+                match &callback_query.message {
+                    MaybeInaccessibleMessage::Message(m) => Some(m),
+                    MaybeInaccessibleMessage::InaccessibleMessage(_) => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// The chat the update happened in.
+    pub fn chat(&self) -> Option<&Chat> {
+        if let Some(message) = self.message() {
+            return Some(&message.chat);
+        }
+        match &self.0 {
+            UpdateData::CallbackQuery { callback_query } if has_message(callback_query) => {
+                match &callback_query.message {
+                    MaybeInaccessibleMessage::InaccessibleMessage(m) => Some(&m.chat),
+                    MaybeInaccessibleMessage::Message(_) => None,
+                }
+            }
+            UpdateData::DeletedBusinessMessages {
+                deleted_business_messages,
+            } => Some(&deleted_business_messages.chat),
+            UpdateData::MessageReaction { message_reaction } => Some(&message_reaction.chat),
+            UpdateData::MessageReactionCount {
+                message_reaction_count,
+            } => Some(&message_reaction_count.chat),
+            UpdateData::MyChatMember { my_chat_member: m }
+            | UpdateData::ChatMember { chat_member: m } => Some(&m.chat),
+            UpdateData::ChatJoinRequest { chat_join_request } => Some(&chat_join_request.chat),
+            UpdateData::ChatBoost { chat_boost } => Some(&chat_boost.chat),
+            UpdateData::RemovedChatBoost { removed_chat_boost } => Some(&removed_chat_boost.chat),
+            _ => None,
+        }
+    }
+
+    /// The user who caused the update, the sender for messages.
+    pub fn user(&self) -> Option<&User> {
+        match &self.0 {
+            UpdateData::CallbackQuery { callback_query } => Some(&callback_query.from),
+            UpdateData::InlineQuery { inline_query } => Some(&inline_query.from),
+            UpdateData::ChosenInlineResult {
+                chosen_inline_result,
+            } => Some(&chosen_inline_result.from),
+            UpdateData::ShippingQuery { shipping_query } => Some(&shipping_query.from),
+            UpdateData::PreCheckoutQuery { pre_checkout_query } => Some(&pre_checkout_query.from),
+            UpdateData::PollAnswer { poll_answer } => poll_answer.user.as_ref(),
+            UpdateData::MessageReaction { message_reaction } => message_reaction.user.as_ref(),
+            UpdateData::MyChatMember { my_chat_member: m }
+            | UpdateData::ChatMember { chat_member: m } => Some(&m.from),
+            UpdateData::ChatJoinRequest { chat_join_request } => Some(&chat_join_request.from),
+            UpdateData::BusinessConnection {
+                business_connection,
+            } => Some(&business_connection.user),
+            _ => self.message().and_then(|message| message.from.as_ref()),
+        }
+    }
+}
+
+/// Raw telegram details of the update behind a matcher, see `UpdateEvent`.
+pub trait TelegramMatcherExt {
+    fn update_event(&self) -> Option<&UpdateEvent>;
+
+    /// Reply, forward origin, thread id, via bot etc. are found here.
+    fn raw_message(&self) -> Option<&telegram_bot_api_rs::available_types::Message> {
+        self.update_event()?.message()
+    }
+
+    fn raw_chat(&self) -> Option<&Chat> {
+        self.update_event()?.chat()
+    }
+
+    fn raw_user(&self) -> Option<&User> {
+        self.update_event()?.user()
+    }
+
+    /// The update behind the `MessageDeletedEvent`s of deleted business messages.
+    fn deleted_business_messages(&self) -> Option<&BusinessMessagesDeleted> {
+        match &self.update_event()?.0 {
+            UpdateData::DeletedBusinessMessages {
+                deleted_business_messages,
+            } => Some(deleted_business_messages),
+            _ => None,
+        }
+    }
+
+    /// Set for messages a business account received or sent through the bot,
+    /// and for deletions of such messages.
+    fn business_connection_id(&self) -> Option<&str> {
+        if let Some(deleted) = self.deleted_business_messages() {
+            return Some(&deleted.business_connection_id);
+        }
+        self.raw_message()?.business_connection_id.as_deref()
+    }
+}
+
+impl TelegramMatcherExt for Matcher {
+    fn update_event(&self) -> Option<&UpdateEvent> {
+        UpdateEvent::from_matcher(self)
     }
 }
 
 impl EventTrait for UpdateEvent {
     fn get_events(&self) -> Vec<Event> {
-        parse_update(self.0.clone())
+        parse_update(self.0.clone(), &self.1, &self.2)
     }
 
     fn server(&self) -> &'static str {
@@ -48,7 +201,7 @@ impl EventTrait for UpdateEvent {
     }
 
     fn clone_box(&self) -> EventObject {
-        Box::new(UpdateEvent(self.0.clone()))
+        Box::new(UpdateEvent(self.0.clone(), self.1.clone(), self.2.clone()))
     }
 
     fn as_any(&self) -> &dyn std::any::Any {
@@ -56,45 +209,187 @@ impl EventTrait for UpdateEvent {
     }
 }
 
-pub struct BussinessConnectionEventWrapper(pub BusinessConnection);
+/// Type of the `AnyEvent`s this crate emits, its `r#type` being the variant name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TelegramEventKind {
+    ChatBoost,
+    RemovedChatBoost,
+    MessageReactionCount,
+    InlineQuery,
+    ChosenInlineResult,
+    CallbackQuery,
+    ShippingQuery,
+    PreCheckoutQuery,
+    Poll,
+    PollAnswer,
+    BusinessConnection,
+    SuccessfulPayment,
+    RefundedPayment,
+}
 
-impl AnyEventDataTrait for BussinessConnectionEventWrapper {
-    fn clone_box(&self) -> Box<dyn AnyEventDataTrait> {
-        Box::new(BussinessConnectionEventWrapper(self.0.clone()))
+impl TelegramEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ChatBoost => "ChatBoost",
+            Self::RemovedChatBoost => "RemovedChatBoost",
+            Self::MessageReactionCount => "MessageReactionCount",
+            Self::InlineQuery => "InlineQuery",
+            Self::ChosenInlineResult => "ChosenInlineResult",
+            Self::CallbackQuery => "CallbackQuery",
+            Self::ShippingQuery => "ShippingQuery",
+            Self::PreCheckoutQuery => "PreCheckoutQuery",
+            Self::Poll => "Poll",
+            Self::PollAnswer => "PollAnswer",
+            Self::BusinessConnection => "BusinessConnection",
+            Self::SuccessfulPayment => "SuccessfulPayment",
+            Self::RefundedPayment => "RefundedPayment",
+        }
     }
+}
 
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
+impl std::fmt::Display for TelegramEventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
     }
 }
 
-pub struct ChatBoostEventWrapper(pub ChatBoostUpdated);
+impl std::str::FromStr for TelegramEventKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "ChatBoost" => Self::ChatBoost,
+            "RemovedChatBoost" => Self::RemovedChatBoost,
+            "MessageReactionCount" => Self::MessageReactionCount,
+            "InlineQuery" => Self::InlineQuery,
+            "ChosenInlineResult" => Self::ChosenInlineResult,
+            "CallbackQuery" => Self::CallbackQuery,
+            "ShippingQuery" => Self::ShippingQuery,
+            "PreCheckoutQuery" => Self::PreCheckoutQuery,
+            "Poll" => Self::Poll,
+            "PollAnswer" => Self::PollAnswer,
+            "BusinessConnection" => Self::BusinessConnection,
+            "SuccessfulPayment" => Self::SuccessfulPayment,
+            "RefundedPayment" => Self::RefundedPayment,
+            _ => return Err(anyhow::anyhow!("Unknown telegram event kind: {}", s)),
+        })
+    }
+}
 
-impl AnyEventDataTrait for ChatBoostEventWrapper {
-    fn clone_box(&self) -> Box<dyn AnyEventDataTrait> {
-        Box::new(ChatBoostEventWrapper(self.0.clone()))
+/// Typed access to the telegram `AnyEvent`s, instead of matching on `r#type` and downcasting.
+/// Every accessor returns `None` for events of another kind or server.
+pub trait TelegramEventExt {
+    fn as_any_event(&self) -> Option<&AnyEvent>;
+
+    fn telegram_kind(&self) -> Option<TelegramEventKind> {
+        let event = self.as_any_event().filter(|event| event.server == SERVER)?;
+        event.r#type.parse().ok()
     }
 
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
+    fn as_chat_boost(&self) -> Option<&ChatBoostUpdated> {
+        downcast::<ChatBoostEventWrapper>(self.as_any_event(), TelegramEventKind::ChatBoost)
+            .map(|wrapper| &wrapper.0)
+    }
+
+    fn as_removed_chat_boost(&self) -> Option<&ChatBoostRemoved> {
+        downcast::<RemovedChatBoostEventWrapper>(
+            self.as_any_event(),
+            TelegramEventKind::RemovedChatBoost,
+        )
+        .map(|wrapper| &wrapper.0)
+    }
+
+    fn as_message_reaction_count(&self) -> Option<&MessageReactionCountUpdated> {
+        downcast::<MessageReactionCountEventWrapper>(
+            self.as_any_event(),
+            TelegramEventKind::MessageReactionCount,
+        )
+        .map(|wrapper| &wrapper.0)
+    }
+
+    fn as_inline_query(&self) -> Option<&InlineQueryEvent> {
+        downcast(self.as_any_event(), TelegramEventKind::InlineQuery)
+    }
+
+    fn as_chosen_inline_result(&self) -> Option<&ChosenInlineResultEvent> {
+        downcast(self.as_any_event(), TelegramEventKind::ChosenInlineResult)
+    }
+
+    fn as_callback_query(&self) -> Option<&CallbackQueryEvent> {
+        downcast(self.as_any_event(), TelegramEventKind::CallbackQuery)
+    }
+
+    fn as_shipping_query(&self) -> Option<&ShippingQuery> {
+        downcast::<ShippingQueryEventWrapper>(self.as_any_event(), TelegramEventKind::ShippingQuery)
+            .map(|wrapper| &wrapper.0)
+    }
+
+    fn as_pre_checkout_query(&self) -> Option<&PreCheckoutQuery> {
+        downcast::<PreCheckoutQueryEventWrapper>(
+            self.as_any_event(),
+            TelegramEventKind::PreCheckoutQuery,
+        )
+        .map(|wrapper| &wrapper.0)
+    }
+
+    fn as_successful_payment(&self) -> Option<&SuccessfulPaymentEvent> {
+        downcast(self.as_any_event(), TelegramEventKind::SuccessfulPayment)
+    }
+
+    fn as_refunded_payment(&self) -> Option<&RefundedPaymentEvent> {
+        downcast(self.as_any_event(), TelegramEventKind::RefundedPayment)
+    }
+
+    fn as_poll(&self) -> Option<&Poll> {
+        downcast::<PollEventWrapper>(self.as_any_event(), TelegramEventKind::Poll)
+            .map(|wrapper| &wrapper.0)
+    }
+
+    fn as_poll_answer(&self) -> Option<&PollAnswer> {
+        downcast::<PollAnswerEventWrapper>(self.as_any_event(), TelegramEventKind::PollAnswer)
+            .map(|wrapper| &wrapper.0)
+    }
+
+    fn as_business_connection(&self) -> Option<&BusinessConnection> {
+        downcast::<BussinessConnectionEventWrapper>(
+            self.as_any_event(),
+            TelegramEventKind::BusinessConnection,
+        )
+        .map(|wrapper| &wrapper.0)
     }
 }
 
-pub struct RemovedChatBoostEventWrapper(pub ChatBoostRemoved);
-impl AnyEventDataTrait for RemovedChatBoostEventWrapper {
-    fn clone_box(&self) -> Box<dyn AnyEventDataTrait> {
-        Box::new(RemovedChatBoostEventWrapper(self.0.clone()))
+impl TelegramEventExt for AnyEvent {
+    fn as_any_event(&self) -> Option<&AnyEvent> {
+        Some(self)
     }
+}
 
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
+impl TelegramEventExt for Event {
+    fn as_any_event(&self) -> Option<&AnyEvent> {
+        match self {
+            Event::AnyEvent(event) => Some(event),
+            _ => None,
+        }
     }
 }
 
-pub struct MessageReactionCountEventWrapper(pub MessageReactionCountUpdated);
-impl AnyEventDataTrait for MessageReactionCountEventWrapper {
+impl TelegramEventExt for Matcher {
+    fn as_any_event(&self) -> Option<&AnyEvent> {
+        self.event.as_any_event()
+    }
+}
+
+fn downcast<T: 'static>(event: Option<&AnyEvent>, kind: TelegramEventKind) -> Option<&T> {
+    let event = event.filter(|event| event.server == SERVER && event.r#type == kind.as_str())?;
+    event.data.as_any().downcast_ref()
+}
+
+pub struct BussinessConnectionEventWrapper(pub BusinessConnection);
+
+impl AnyEventDataTrait for BussinessConnectionEventWrapper {
     fn clone_box(&self) -> Box<dyn AnyEventDataTrait> {
-        Box::new(MessageReactionCountEventWrapper(self.0.clone()))
+        Box::new(BussinessConnectionEventWrapper(self.0.clone()))
     }
 
     fn as_any(&self) -> &dyn std::any::Any {
@@ -102,10 +397,11 @@ impl AnyEventDataTrait for MessageReactionCountEventWrapper {
     }
 }
 
-pub struct InlineQueryEventWrapper(pub InlineQuery);
-impl AnyEventDataTrait for InlineQueryEventWrapper {
+pub struct ChatBoostEventWrapper(pub ChatBoostUpdated);
+
+impl AnyEventDataTrait for ChatBoostEventWrapper {
     fn clone_box(&self) -> Box<dyn AnyEventDataTrait> {
-        Box::new(InlineQueryEventWrapper(self.0.clone()))
+        Box::new(ChatBoostEventWrapper(self.0.clone()))
     }
 
     fn as_any(&self) -> &dyn std::any::Any {
@@ -113,10 +409,10 @@ impl AnyEventDataTrait for InlineQueryEventWrapper {
     }
 }
 
-pub struct ChosenInlineResultEventWrapper(pub ChosenInlineResult);
-impl AnyEventDataTrait for ChosenInlineResultEventWrapper {
+pub struct RemovedChatBoostEventWrapper(pub ChatBoostRemoved);
+impl AnyEventDataTrait for RemovedChatBoostEventWrapper {
     fn clone_box(&self) -> Box<dyn AnyEventDataTrait> {
-        Box::new(ChosenInlineResultEventWrapper(self.0.clone()))
+        Box::new(RemovedChatBoostEventWrapper(self.0.clone()))
     }
 
     fn as_any(&self) -> &dyn std::any::Any {
@@ -124,10 +420,10 @@ impl AnyEventDataTrait for ChosenInlineResultEventWrapper {
     }
 }
 
-pub struct PreCheckoutQueryEventWrapper(pub PreCheckoutQuery);
-impl AnyEventDataTrait for PreCheckoutQueryEventWrapper {
+pub struct MessageReactionCountEventWrapper(pub MessageReactionCountUpdated);
+impl AnyEventDataTrait for MessageReactionCountEventWrapper {
     fn clone_box(&self) -> Box<dyn AnyEventDataTrait> {
-        Box::new(PreCheckoutQueryEventWrapper(self.0.clone()))
+        Box::new(MessageReactionCountEventWrapper(self.0.clone()))
     }
 
     fn as_any(&self) -> &dyn std::any::Any {
@@ -135,10 +431,10 @@ impl AnyEventDataTrait for PreCheckoutQueryEventWrapper {
     }
 }
 
-pub struct CallbackQueryEventWrapper(pub telegram_bot_api_rs::available_types::CallbackQuery);
-impl AnyEventDataTrait for CallbackQueryEventWrapper {
+pub struct PreCheckoutQueryEventWrapper(pub PreCheckoutQuery);
+impl AnyEventDataTrait for PreCheckoutQueryEventWrapper {
     fn clone_box(&self) -> Box<dyn AnyEventDataTrait> {
-        Box::new(CallbackQueryEventWrapper(self.0.clone()))
+        Box::new(PreCheckoutQueryEventWrapper(self.0.clone()))
     }
 
     fn as_any(&self) -> &dyn std::any::Any {
@@ -157,7 +453,7 @@ impl AnyEventDataTrait for ShippingQueryEventWrapper {
     }
 }
 
-pub struct PollEventWrapper(pub telegram_bot_api_rs::available_types::Poll);
+pub struct PollEventWrapper(pub Poll);
 impl AnyEventDataTrait for PollEventWrapper {
     fn clone_box(&self) -> Box<dyn AnyEventDataTrait> {
         Box::new(PollEventWrapper(self.0.clone()))
@@ -168,7 +464,7 @@ impl AnyEventDataTrait for PollEventWrapper {
     }
 }
 
-pub struct PollAnswerEventWrapper(pub telegram_bot_api_rs::available_types::PollAnswer);
+pub struct PollAnswerEventWrapper(pub PollAnswer);
 
 impl AnyEventDataTrait for PollAnswerEventWrapper {
     fn clone_box(&self) -> Box<dyn AnyEventDataTrait> {
@@ -180,22 +476,22 @@ impl AnyEventDataTrait for PollAnswerEventWrapper {
     }
 }
 
-pub struct DeletedBusinessMessagesEventWrapper(pub BusinessMessagesDeleted);
-impl AnyEventDataTrait for DeletedBusinessMessagesEventWrapper {
-    fn clone_box(&self) -> Box<dyn AnyEventDataTrait> {
-        Box::new(DeletedBusinessMessagesEventWrapper(self.0.clone()))
-    }
+/// A `MessageDeletedEvent` for a message deleted by the bot through `delete_message`,
+/// emitted if `TelegramBotOptions::echo_deleted_messages` is set since Telegram sends no update.
+#[derive(Clone)]
+pub struct BotDeletionEvent(pub Event);
 
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
+impl EventTrait for BotDeletionEvent {
+    fn get_events(&self) -> Vec<Event> {
+        vec![self.0.clone()]
     }
-}
 
-pub struct EditedBusinessMessageEventWrapper(pub telegram_bot_api_rs::available_types::Message);
+    fn server(&self) -> &'static str {
+        SERVER
+    }
 
-impl AnyEventDataTrait for EditedBusinessMessageEventWrapper {
-    fn clone_box(&self) -> Box<dyn AnyEventDataTrait> {
-        Box::new(EditedBusinessMessageEventWrapper(self.0.clone()))
+    fn clone_box(&self) -> EventObject {
+        Box::new(self.clone())
     }
 
     fn as_any(&self) -> &dyn std::any::Any {
@@ -203,13 +499,48 @@ impl AnyEventDataTrait for EditedBusinessMessageEventWrapper {
     }
 }
 
-pub fn parse_update(update: UpdateData) -> Vec<Event> {
+/// A `MessageDeletedEvent`, with the content and sender of the message if it was recorded.
+/// `chat_user` is the other user of a private chat, the `user` of the event when the message
+/// wasn't recorded so that it can still be replied to.
+pub(crate) fn message_deleted_event(
+    message_id: String,
+    group: Option<Group>,
+    chat_user: Option<oxidebot::source::user::User>,
+    operator: Option<oxidebot::source::user::User>,
+    messages: &MessageStore,
+    users: &UserCache,
+) -> Event {
+    let recorded = messages.get(&message_id);
+    Event::NoticeEvent(oxidebot::event::NoticeEvent::MessageDeletedEvent(
+        MessageDeletedEvent {
+            user: match &recorded {
+                Some(message) => message.from.clone().map(parse_user),
+                None => chat_user,
+            },
+            operator,
+            group,
+            message: Some(match recorded {
+                Some(message) => parse_message(message, users),
+                None => Message {
+                    id: message_id,
+                    segments: Vec::with_capacity(0),
+                },
+            }),
+        },
+    ))
+}
+
+pub fn parse_update(update: UpdateData, users: &UserCache, messages: &MessageStore) -> Vec<Event> {
     let mut results = Vec::new();
     match update {
-        UpdateData::Message { message } => {
+        // Business messages are told apart by their `business_connection_id`
+        UpdateData::Message { message }
+        | UpdateData::BusinessMessage {
+            business_message: message,
+        } => {
             if let Some(user) = message.from.clone().and_then(|f| Some(parse_user(f))) {
                 results.push(Event::MessageEvent(MessageEvent {
-                    id: format!("{}_{}", message.chat.id, message.message_id),
+                    id: utils::message_id(&message),
                     time: DateTime::from_timestamp(message.date, 0),
                     sender: user,
                     group: {
@@ -219,7 +550,21 @@ pub fn parse_update(update: UpdateData) -> Vec<Event> {
                             Some(parse_group(message.chat.clone()))
                         }
                     },
-                    message: segment::parse_message(message.clone()),
+                    message: segment::parse_message(message.clone(), users),
+                }));
+            }
+            if let Some(payment) = message.successful_payment.clone() {
+                results.push(Event::AnyEvent(AnyEvent {
+                    server: SERVER,
+                    r#type: TelegramEventKind::SuccessfulPayment.to_string(),
+                    data: Box::new(SuccessfulPaymentEvent::new(&message, payment)),
+                }));
+            }
+            if let Some(payment) = message.refunded_payment.clone() {
+                results.push(Event::AnyEvent(AnyEvent {
+                    server: SERVER,
+                    r#type: TelegramEventKind::RefundedPayment.to_string(),
+                    data: Box::new(RefundedPaymentEvent::new(&message, payment)),
                 }));
             }
             if let Some(new_chatmembers) = message.new_chat_members {
@@ -249,6 +594,9 @@ pub fn parse_update(update: UpdateData) -> Vec<Event> {
         }
         UpdateData::EditedMessage {
             edited_message: message,
+        }
+        | UpdateData::EditedBusinessMessage {
+            edited_business_message: message,
         } => {
             if let Some(user) = message.from.clone().and_then(|f| Some(parse_user(f))) {
                 results.push(Event::NoticeEvent(
@@ -261,7 +609,7 @@ pub fn parse_update(update: UpdateData) -> Vec<Event> {
                                 Some(parse_group(message.chat.clone()))
                             }
                         },
-                        new_message: Some(parse_message(message.clone())),
+                        new_message: Some(parse_message(message.clone(), users)),
                         operator: {
                             if let Some(user) = message.from.clone() {
                                 Some(parse_user(user))
@@ -269,7 +617,9 @@ pub fn parse_update(update: UpdateData) -> Vec<Event> {
                                 None
                             }
                         },
-                        old_message: None,
+                        old_message: messages
+                            .get(&utils::message_id(&message))
+                            .map(|old| parse_message(old, users)),
                     }),
                 ));
             }
@@ -277,7 +627,7 @@ pub fn parse_update(update: UpdateData) -> Vec<Event> {
         UpdateData::ChannelPost { channel_post } => {
             if let Some(user) = channel_post.from.clone().and_then(|f| Some(parse_user(f))) {
                 results.push(Event::MessageEvent(MessageEvent {
-                    id: format!("{}_{}", channel_post.chat.id, channel_post.message_id),
+                    id: utils::message_id(&channel_post),
                     time: DateTime::from_timestamp(channel_post.date, 0),
                     sender: user,
                     group: {
@@ -287,7 +637,7 @@ pub fn parse_update(update: UpdateData) -> Vec<Event> {
                             Some(parse_group(channel_post.chat.clone()))
                         }
                     },
-                    message: segment::parse_message(channel_post),
+                    message: segment::parse_message(channel_post, users),
                 }));
             }
         }
@@ -309,7 +659,7 @@ pub fn parse_update(update: UpdateData) -> Vec<Event> {
                                 Some(parse_group(edited_channel_post.chat.clone()))
                             }
                         },
-                        new_message: Some(parse_message(edited_channel_post.clone())),
+                        new_message: Some(parse_message(edited_channel_post.clone(), users)),
                         operator: {
                             if let Some(user) = edited_channel_post.from.clone() {
                                 Some(parse_user(user))
@@ -317,7 +667,9 @@ pub fn parse_update(update: UpdateData) -> Vec<Event> {
                                 None
                             }
                         },
-                        old_message: None,
+                        old_message: messages
+                            .get(&utils::message_id(&edited_channel_post))
+                            .map(|old| parse_message(old, users)),
                     }),
                 ));
             }
@@ -491,7 +843,10 @@ pub fn parse_update(update: UpdateData) -> Vec<Event> {
         UpdateData::ChatJoinRequest { chat_join_request } => results.push({
             Event::RequestEvent(oxidebot::event::RequestEvent::GroupAddEvent(
                 GroupAddEvent {
-                    id: chat_join_request.user_chat_id.to_string(),
+                    id: format!(
+                        "{}_{}",
+                        chat_join_request.chat.id, chat_join_request.from.id
+                    ),
                     user: parse_user(chat_join_request.from),
                     group: parse_group(chat_join_request.chat),
                     message: chat_join_request.bio,
@@ -500,13 +855,13 @@ pub fn parse_update(update: UpdateData) -> Vec<Event> {
         }),
         UpdateData::ChatBoost { chat_boost } => results.push(Event::AnyEvent(AnyEvent {
             server: SERVER,
-            r#type: "ChatBoost".to_string(),
+            r#type: TelegramEventKind::ChatBoost.to_string(),
             data: Box::new(ChatBoostEventWrapper(chat_boost)),
         })),
         UpdateData::RemovedChatBoost { removed_chat_boost } => {
             results.push(Event::AnyEvent(AnyEvent {
                 server: SERVER,
-                r#type: "RemovedChatBoost".to_string(),
+                r#type: TelegramEventKind::RemovedChatBoost.to_string(),
                 data: Box::new(RemovedChatBoostEventWrapper(removed_chat_boost)),
             }))
         }
@@ -514,79 +869,135 @@ pub fn parse_update(update: UpdateData) -> Vec<Event> {
             message_reaction_count,
         } => results.push(Event::AnyEvent(AnyEvent {
             server: SERVER,
-            r#type: "MessageReactionCount".to_string(),
+            r#type: TelegramEventKind::MessageReactionCount.to_string(),
             data: Box::new(MessageReactionCountEventWrapper(message_reaction_count)),
         })),
         UpdateData::InlineQuery { inline_query } => results.push(Event::AnyEvent(AnyEvent {
             server: SERVER,
-            r#type: "InlineQuery".to_string(),
-            data: Box::new(InlineQueryEventWrapper(inline_query)),
+            r#type: TelegramEventKind::InlineQuery.to_string(),
+            data: Box::new(InlineQueryEvent::from(inline_query)),
         })),
         UpdateData::ChosenInlineResult {
             chosen_inline_result,
         } => results.push(Event::AnyEvent(AnyEvent {
             server: SERVER,
-            r#type: "ChosenInlineResult".to_string(),
-            data: Box::new(ChosenInlineResultEventWrapper(chosen_inline_result)),
+            r#type: TelegramEventKind::ChosenInlineResult.to_string(),
+            data: Box::new(ChosenInlineResultEvent::from(chosen_inline_result)),
         })),
         UpdateData::CallbackQuery { callback_query } => results.push(Event::AnyEvent(AnyEvent {
             server: SERVER,
-            r#type: "CallbackQuery".to_string(),
-            data: Box::new(CallbackQueryEventWrapper(callback_query)),
+            r#type: TelegramEventKind::CallbackQuery.to_string(),
+            data: Box::new(CallbackQueryEvent::from(callback_query)),
         })),
         UpdateData::ShippingQuery { shipping_query } => results.push(Event::AnyEvent(AnyEvent {
             server: SERVER,
-            r#type: "ShippingQuery".to_string(),
+            r#type: TelegramEventKind::ShippingQuery.to_string(),
             data: Box::new(ShippingQueryEventWrapper(shipping_query)),
         })),
         UpdateData::PreCheckoutQuery { pre_checkout_query } => {
             results.push(Event::AnyEvent(AnyEvent {
                 server: SERVER,
-                r#type: "PreCheckoutQuery".to_string(),
+                r#type: TelegramEventKind::PreCheckoutQuery.to_string(),
                 data: Box::new(PreCheckoutQueryEventWrapper(pre_checkout_query)),
             }))
         }
         UpdateData::Poll { poll } => results.push(Event::AnyEvent(AnyEvent {
             server: SERVER,
-            r#type: "Poll".to_string(),
+            r#type: TelegramEventKind::Poll.to_string(),
             data: Box::new(PollEventWrapper(poll)),
         })),
         UpdateData::PollAnswer { poll_answer } => results.push(Event::AnyEvent(AnyEvent {
             server: SERVER,
-            r#type: "PollAnswer".to_string(),
+            r#type: TelegramEventKind::PollAnswer.to_string(),
             data: Box::new(PollAnswerEventWrapper(poll_answer)),
         })),
+        // Business chats are private, who deleted the messages isn't told.
+        // The connection is found through `TelegramMatcherExt::business_connection_id`
         UpdateData::DeletedBusinessMessages {
             deleted_business_messages,
-        } => results.push(Event::AnyEvent(AnyEvent {
-            server: SERVER,
-            r#type: "DeletedBusinessMessages".to_string(),
-            data: Box::new(DeletedBusinessMessagesEventWrapper(
-                deleted_business_messages,
-            )),
-        })),
-        UpdateData::EditedBusinessMessage {
-            edited_business_message,
-        } => results.push(Event::AnyEvent(AnyEvent {
-            server: SERVER,
-            r#type: "EditedBusinessMessage".to_string(),
-            data: Box::new(EditedBusinessMessageEventWrapper(edited_business_message)),
-        })),
+        } => {
+            // Business chats are private chats with the customer
+            let customer = parse_private_chat(deleted_business_messages.chat.clone());
+            for message_id in deleted_business_messages.message_ids {
+                results.push(message_deleted_event(
+                    format!("{}_{}", deleted_business_messages.chat.id, message_id),
+                    None,
+                    Some(customer.clone()),
+                    None,
+                    messages,
+                    users,
+                ));
+            }
+        }
         UpdateData::BusinessConnection {
             business_connection,
         } => results.push(Event::AnyEvent(AnyEvent {
             server: SERVER,
-            r#type: "BusinessConnection".to_string(),
+            r#type: TelegramEventKind::BusinessConnection.to_string(),
             data: Box::new(BussinessConnectionEventWrapper(business_connection)),
         })),
-        UpdateData::BusinessMessage { business_message } => {
-            results.push(Event::AnyEvent(AnyEvent {
-                server: SERVER,
-                r#type: "BusinessMessage".to_string(),
-                data: Box::new(EditedBusinessMessageEventWrapper(business_message)),
-            }))
-        }
     }
 
     results
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_kinds_round_trip_through_their_names() {
+        use TelegramEventKind::*;
+        let kinds = [
+            ChatBoost,
+            RemovedChatBoost,
+            MessageReactionCount,
+            InlineQuery,
+            ChosenInlineResult,
+            CallbackQuery,
+            ShippingQuery,
+            PreCheckoutQuery,
+            Poll,
+            PollAnswer,
+            BusinessConnection,
+            SuccessfulPayment,
+            RefundedPayment,
+        ];
+        for kind in kinds {
+            // Fails to compile when a variant is missing from `kinds`
+            match kind {
+                ChatBoost | RemovedChatBoost | MessageReactionCount | InlineQuery
+                | ChosenInlineResult | CallbackQuery | ShippingQuery | PreCheckoutQuery | Poll
+                | PollAnswer | BusinessConnection | SuccessfulPayment | RefundedPayment => {}
+            }
+            assert_eq!(kind.as_str().parse::<TelegramEventKind>().unwrap(), kind);
+            assert_eq!(kind.to_string(), kind.as_str());
+        }
+        assert!("Unknown".parse::<TelegramEventKind>().is_err());
+    }
+
+    #[test]
+    fn unrecorded_deleted_business_messages_come_from_the_customer() {
+        let update = UpdateData::DeletedBusinessMessages {
+            deleted_business_messages: serde_json::from_value(serde_json::json!({
+                "business_connection_id": "connection",
+                "chat": { "id": 42, "type": "private", "username": "bob" },
+                "message_ids": [7],
+            }))
+            .unwrap(),
+        };
+        let events = parse_update(update, &UserCache::default(), &MessageStore::new(0, None));
+        let [Event::NoticeEvent(oxidebot::event::NoticeEvent::MessageDeletedEvent(event))] =
+            &events[..]
+        else {
+            panic!("expected a single message deleted event");
+        };
+        let user = event.user.as_ref().unwrap();
+        assert_eq!(user.id, "42");
+        assert_eq!(
+            user.profile.as_ref().unwrap().nickname.as_deref(),
+            Some("bob")
+        );
+        assert_eq!(event.message.as_ref().unwrap().id, "42_7");
+    }
+}
@@ -0,0 +1,353 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use anyhow::Result;
+use oxidebot::source::message::File;
+use reqwest::multipart::Form;
+use telegram_bot_api_rs::{
+    available_methods::payload::{
+        ChatIdPayload, CreateForumTopicPayload, DeleteChatPhotoPayload,
+        DeleteChatStickerSetPayload, EditForumTopicPayload, ForumTopicPayload,
+        GetChatMemberPayload, PromoteChatMemberPayload, SetChatAdministratorCustomTitlePayload,
+        SetChatDescriptionPayload, SetChatPermissionsPayload, SetChatStickerSetPayload,
+    },
+    available_types::{ChatAdministratorRights, ChatMember, ChatPermissions, ForumTopic},
+};
+
+use crate::{
+    bot::TelegramBot,
+    error::TelegramError,
+    request::{call_api_multipart, file_to_part},
+};
+
+/// Tells a timed unmute apart from the ones that replaced it.
+static UNMUTE_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Telegram only apis, reachable by downcasting the `BotObject`:
+/// `bot.as_any().downcast_ref::<TelegramBot>()`
+impl TelegramBot {
+    /// Promote a group member with the given rights, or with `TelegramBotOptions::admin_rights` if `None`.
+    pub async fn promote_group_member(
+        &self,
+        group_id: String,
+        user_id: String,
+        rights: Option<ChatAdministratorRights>,
+    ) -> Result<()> {
+        let rights = rights.unwrap_or_else(|| self.options.admin_rights.clone());
+        self.bot
+            .promote_chat_member(&promote_payload(group_id, user_id.parse()?, rights))
+            .await?;
+        Ok(())
+    }
+
+    /// Demote a group admin by revoking all administrator rights.
+    pub async fn demote_group_member(&self, group_id: String, user_id: String) -> Result<()> {
+        let rights = ChatAdministratorRights {
+            is_anonymous: Some(false),
+            can_manage_chat: Some(false),
+            can_delete_messages: Some(false),
+            can_manage_video_chats: Some(false),
+            can_restrict_members: Some(false),
+            can_promote_members: Some(false),
+            can_change_info: Some(false),
+            can_invite_users: Some(false),
+            can_post_stories: Some(false),
+            can_edit_stories: Some(false),
+            can_delete_stories: Some(false),
+            can_post_messages: Some(false),
+            can_edit_messages: Some(false),
+            can_pin_messages: Some(false),
+            can_manage_topics: Some(false),
+        };
+        self.bot
+            .promote_chat_member(&promote_payload(group_id, user_id.parse()?, rights))
+            .await?;
+        Ok(())
+    }
+
+    /// Set the custom title of a group admin.
+    /// Non-admins are promoted with minimal rights first if `TelegramBotOptions::promote_for_alias` is set,
+    /// otherwise `TelegramError::NotAdministrator` is returned.
+    pub async fn set_admin_custom_title(
+        &self,
+        group_id: String,
+        user_id: String,
+        title: String,
+    ) -> Result<()> {
+        let user_id_num = user_id.parse()?;
+        let member = self
+            .bot
+            .get_chat_member(&GetChatMemberPayload {
+                chat_id: group_id.clone(),
+                user_id: user_id_num,
+            })
+            .await?;
+        if !matches!(
+            member,
+            ChatMember::Administrator { .. } | ChatMember::Owner { .. }
+        ) {
+            if !self.options.promote_for_alias {
+                return Err(TelegramError::NotAdministrator { group_id, user_id }.into());
+            }
+            self.promote_group_member(
+                group_id.clone(),
+                user_id,
+                Some(ChatAdministratorRights {
+                    can_invite_users: Some(true),
+                    ..Default::default()
+                }),
+            )
+            .await?;
+        }
+        self.bot
+            .set_chat_administrator_custom_title(&SetChatAdministratorCustomTitlePayload {
+                chat_id: group_id,
+                user_id: user_id_num,
+                custom_title: title,
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Upload a new group photo, the content is read from the file's base64, uri or id.
+    pub async fn set_group_photo(&self, group_id: String, photo: File) -> Result<()> {
+        let form = Form::new()
+            .text("chat_id", group_id)
+            .part("photo", file_to_part(&self.bot, &photo).await?);
+        call_api_multipart::<bool>(&self.bot, "setChatPhoto", form).await?;
+        Ok(())
+    }
+
+    pub async fn delete_group_photo(&self, group_id: String) -> Result<()> {
+        self.bot
+            .delete_chat_photo(&DeleteChatPhotoPayload { chat_id: group_id })
+            .await?;
+        Ok(())
+    }
+
+    /// Set the group description, `None` clears it.
+    pub async fn set_group_description(
+        &self,
+        group_id: String,
+        description: Option<String>,
+    ) -> Result<()> {
+        self.bot
+            .set_chat_description(&SetChatDescriptionPayload {
+                chat_id: group_id,
+                description,
+            })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_group_sticker_set(
+        &self,
+        group_id: String,
+        sticker_set_name: String,
+    ) -> Result<()> {
+        self.bot
+            .set_chat_sticker_set(&SetChatStickerSetPayload {
+                chat_id: group_id,
+                sticker_set_name,
+            })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_group_sticker_set(&self, group_id: String) -> Result<()> {
+        self.bot
+            .delete_chat_sticker_set(&DeleteChatStickerSetPayload { chat_id: group_id })
+            .await?;
+        Ok(())
+    }
+
+    /// Forbid all members from sending anything, snapshotting the current permissions first.
+    /// Muting an already muted group keeps the original snapshot.
+    pub async fn mute_whole_group(&self, group_id: String) -> Result<()> {
+        self.cancel_unmute(&group_id);
+        if self.permission_store.get(&group_id).await.is_none() {
+            let permissions = self
+                .bot
+                .get_chat(&ChatIdPayload {
+                    chat_id: group_id.clone(),
+                })
+                .await?
+                .permissions
+                .ok_or(anyhow::anyhow!("Failed to get permissions of group"))?;
+            self.permission_store
+                .insert(group_id.clone(), permissions)
+                .await?;
+        }
+        self.bot
+            .set_chat_permissions(&SetChatPermissionsPayload {
+                chat_id: group_id,
+                permissions: ChatPermissions {
+                    can_send_messages: Some(false),
+                    can_send_audios: Some(false),
+                    can_send_documents: Some(false),
+                    can_send_photos: Some(false),
+                    can_send_videos: Some(false),
+                    can_send_video_notes: Some(false),
+                    can_send_voice_notes: Some(false),
+                    can_send_polls: Some(false),
+                    can_send_other_messages: Some(false),
+                    can_add_web_page_previews: Some(false),
+                    can_change_info: Some(false),
+                    can_invite_users: Some(false),
+                    can_pin_messages: Some(false),
+                    can_manage_topics: Some(false),
+                },
+                use_independent_chat_permissions: Some(true),
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Restore the permissions snapshotted by `mute_whole_group`.
+    /// Returns `false` if there was no snapshot for the group.
+    pub async fn unmute_whole_group(&self, group_id: String) -> Result<bool> {
+        self.cancel_unmute(&group_id);
+        let Some(permissions) = self.permission_store.get(&group_id).await else {
+            return Ok(false);
+        };
+        self.bot
+            .set_chat_permissions(&SetChatPermissionsPayload {
+                chat_id: group_id.clone(),
+                permissions,
+                use_independent_chat_permissions: Some(true),
+            })
+            .await?;
+        self.permission_store.remove(&group_id).await?;
+        Ok(true)
+    }
+
+    /// Unmute a group after `duration`, unless it gets muted or unmuted again in between.
+    pub(crate) fn schedule_unmute(&self, group_id: String, duration: Duration) {
+        let bot = self.clone();
+        // Held until the handle is stored, so the timer always finds itself registered.
+        let mut timers = self.unmute_timers.lock().unwrap();
+        let generation = UNMUTE_GENERATION.fetch_add(1, Ordering::Relaxed);
+        let handle = tokio::spawn({
+            let group_id = group_id.clone();
+            async move {
+                tokio::time::sleep(duration).await;
+                {
+                    let mut timers = bot.unmute_timers.lock().unwrap();
+                    if timers.get(&group_id).map(|(timer, _)| *timer) != Some(generation) {
+                        return;
+                    }
+                    timers.remove(&group_id);
+                }
+                if let Err(e) = bot.unmute_whole_group(group_id).await {
+                    tracing::error!("Error while unmuting group: {:?}", e);
+                }
+            }
+        });
+        if let Some((_, previous)) = timers.insert(group_id, (generation, handle.abort_handle())) {
+            previous.abort();
+        }
+    }
+
+    fn cancel_unmute(&self, group_id: &str) {
+        if let Some((_, timer)) = self.unmute_timers.lock().unwrap().remove(group_id) {
+            timer.abort();
+        }
+    }
+
+    /// Create a topic in a forum group. Send into it with the target `{group_id}_{message_thread_id}`.
+    pub async fn create_forum_topic(
+        &self,
+        group_id: String,
+        name: String,
+        icon_color: Option<i64>,
+        icon_custom_emoji_id: Option<String>,
+    ) -> Result<ForumTopic> {
+        self.bot
+            .create_forum_topic(&CreateForumTopicPayload {
+                chat_id: group_id,
+                name,
+                icon_color,
+                icon_custom_emoji_id,
+            })
+            .await
+    }
+
+    /// Rename a forum topic or change its icon, `None` keeps the current value
+    /// and an empty `icon_custom_emoji_id` removes the icon.
+    pub async fn edit_forum_topic(
+        &self,
+        group_id: String,
+        message_thread_id: i64,
+        name: Option<String>,
+        icon_custom_emoji_id: Option<String>,
+    ) -> Result<()> {
+        self.bot
+            .edit_forum_topic(&EditForumTopicPayload {
+                chat_id: group_id,
+                message_thread_id,
+                name: name.unwrap_or_default(),
+                icon_custom_emoji_id,
+            })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn close_forum_topic(&self, group_id: String, message_thread_id: i64) -> Result<()> {
+        self.bot
+            .close_forum_topic(&ForumTopicPayload {
+                chat_id: group_id,
+                message_thread_id,
+            })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn reopen_forum_topic(&self, group_id: String, message_thread_id: i64) -> Result<()> {
+        self.bot
+            .reopen_forum_topic(&ForumTopicPayload {
+                chat_id: group_id,
+                message_thread_id,
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Delete a forum topic along with all its messages.
+    pub async fn delete_forum_topic(&self, group_id: String, message_thread_id: i64) -> Result<()> {
+        self.bot
+            .delete_forum_topic(&ForumTopicPayload {
+                chat_id: group_id,
+                message_thread_id,
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+fn promote_payload(
+    chat_id: String,
+    user_id: i64,
+    rights: ChatAdministratorRights,
+) -> PromoteChatMemberPayload {
+    PromoteChatMemberPayload {
+        chat_id,
+        user_id,
+        is_anonymous: rights.is_anonymous,
+        can_manage_chat: rights.can_manage_chat,
+        can_delete_messages: rights.can_delete_messages,
+        can_manage_video_chats: rights.can_manage_video_chats,
+        can_restrict_members: rights.can_restrict_members,
+        can_promote_members: rights.can_promote_members,
+        can_change_info: rights.can_change_info,
+        can_invite_users: rights.can_invite_users,
+        can_post_stories: rights.can_post_stories,
+        can_edit_stories: rights.can_edit_stories,
+        can_delete_stories: rights.can_delete_stories,
+        can_post_messages: rights.can_post_messages,
+        can_edit_messages: rights.can_edit_messages,
+        can_pin_messages: rights.can_pin_messages,
+        can_manage_topics: rights.can_manage_topics,
+    }
+}
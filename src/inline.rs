@@ -0,0 +1,324 @@
+use anyhow::Result;
+use oxidebot::{event::any::AnyEventDataTrait, source::user::User};
+use serde_json::{json, Value};
+use telegram_bot_api_rs::inline_mode::types::{
+    ChosenInlineResult, InlineQuery, InlineQueryResultsButton,
+};
+
+use crate::{
+    bot::TelegramBot, keyboard::InlineKeyboard, request::call_api_json, utils::parse_user,
+};
+
+/// Telegram shows at most this many results per answer.
+pub const MAX_INLINE_RESULTS: usize = 50;
+
+/// `@bot query` was typed in a chat, sent as a `TelegramEventKind::InlineQuery` event.
+/// Answer it with `TelegramBot::answer_inline_query`.
+#[derive(Debug, Clone)]
+pub struct InlineQueryEvent {
+    pub id: String,
+    pub sender: User,
+    pub query: String,
+    /// `next_offset` of the previous answer when the user scrolls for more results, empty at first.
+    pub offset: String,
+    /// Type of the chat the query was sent from, `sender` for the private chat with the bot.
+    pub chat_type: Option<String>,
+    pub raw: InlineQuery,
+}
+
+impl From<InlineQuery> for InlineQueryEvent {
+    fn from(query: InlineQuery) -> Self {
+        Self {
+            id: query.id.clone(),
+            sender: parse_user(query.from.clone()),
+            query: query.query.clone(),
+            offset: query.offset.clone(),
+            chat_type: query.chat_type.clone(),
+            raw: query,
+        }
+    }
+}
+
+impl AnyEventDataTrait for InlineQueryEvent {
+    fn clone_box(&self) -> Box<dyn AnyEventDataTrait> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+/// A result of an inline query was sent, as a `TelegramEventKind::ChosenInlineResult` event.
+/// Only delivered once inline feedback is enabled with @BotFather.
+/// See `TelegramBot::chosen_inline_query` for the query it answered.
+#[derive(Debug, Clone)]
+pub struct ChosenInlineResultEvent {
+    pub result_id: String,
+    pub sender: User,
+    pub query: String,
+    /// Set if the result has an inline keyboard, to edit the sent message.
+    pub inline_message_id: Option<String>,
+    pub raw: ChosenInlineResult,
+}
+
+impl From<ChosenInlineResult> for ChosenInlineResultEvent {
+    fn from(result: ChosenInlineResult) -> Self {
+        Self {
+            result_id: result.result_id.clone(),
+            sender: parse_user(result.from.clone()),
+            query: result.query.clone(),
+            inline_message_id: result.inline_message_id.clone(),
+            raw: result,
+        }
+    }
+}
+
+impl AnyEventDataTrait for ChosenInlineResultEvent {
+    fn clone_box(&self) -> Box<dyn AnyEventDataTrait> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+/// Builder of an `InlineQueryResult`.
+/// Built as json so that one builder covers every result type, `field` sets what a type
+/// needs beyond the helpers.
+#[derive(Debug, Clone)]
+pub struct InlineResult(serde_json::Map<String, Value>);
+
+impl InlineResult {
+    fn new(r#type: &str, id: impl Into<String>) -> Self {
+        let mut fields = serde_json::Map::new();
+        fields.insert("type".to_string(), r#type.into());
+        fields.insert("id".to_string(), id.into().into());
+        Self(fields)
+    }
+
+    /// A result sending `text`.
+    pub fn article(
+        id: impl Into<String>,
+        title: impl Into<String>,
+        text: impl Into<String>,
+    ) -> Self {
+        Self::new("article", id).field("title", title.into()).field(
+            "input_message_content",
+            json!({ "message_text": text.into() }),
+        )
+    }
+
+    pub fn photo(
+        id: impl Into<String>,
+        photo_url: impl Into<String>,
+        thumbnail_url: impl Into<String>,
+    ) -> Self {
+        Self::new("photo", id)
+            .field("photo_url", photo_url.into())
+            .field("thumbnail_url", thumbnail_url.into())
+    }
+
+    pub fn gif(
+        id: impl Into<String>,
+        gif_url: impl Into<String>,
+        thumbnail_url: impl Into<String>,
+    ) -> Self {
+        Self::new("gif", id)
+            .field("gif_url", gif_url.into())
+            .field("thumbnail_url", thumbnail_url.into())
+    }
+
+    /// A file behind `document_url`, only `application/pdf` and `application/zip` are accepted.
+    pub fn document(
+        id: impl Into<String>,
+        title: impl Into<String>,
+        document_url: impl Into<String>,
+        mime_type: impl Into<String>,
+    ) -> Self {
+        Self::new("document", id)
+            .field("title", title.into())
+            .field("document_url", document_url.into())
+            .field("mime_type", mime_type.into())
+    }
+
+    pub fn cached_photo(id: impl Into<String>, file_id: impl Into<String>) -> Self {
+        Self::new("photo", id).field("photo_file_id", file_id.into())
+    }
+
+    pub fn cached_gif(id: impl Into<String>, file_id: impl Into<String>) -> Self {
+        Self::new("gif", id).field("gif_file_id", file_id.into())
+    }
+
+    pub fn cached_sticker(id: impl Into<String>, file_id: impl Into<String>) -> Self {
+        Self::new("sticker", id).field("sticker_file_id", file_id.into())
+    }
+
+    pub fn cached_document(
+        id: impl Into<String>,
+        title: impl Into<String>,
+        file_id: impl Into<String>,
+    ) -> Self {
+        Self::new("document", id)
+            .field("title", title.into())
+            .field("document_file_id", file_id.into())
+    }
+
+    pub fn cached_video(
+        id: impl Into<String>,
+        title: impl Into<String>,
+        file_id: impl Into<String>,
+    ) -> Self {
+        Self::new("video", id)
+            .field("title", title.into())
+            .field("video_file_id", file_id.into())
+    }
+
+    pub fn cached_audio(id: impl Into<String>, file_id: impl Into<String>) -> Self {
+        Self::new("audio", id).field("audio_file_id", file_id.into())
+    }
+
+    pub fn title(self, title: impl Into<String>) -> Self {
+        self.field("title", title.into())
+    }
+
+    pub fn description(self, description: impl Into<String>) -> Self {
+        self.field("description", description.into())
+    }
+
+    /// Caption of media results.
+    pub fn caption(self, caption: impl Into<String>) -> Self {
+        self.field("caption", caption.into())
+    }
+
+    pub fn thumbnail_url(self, thumbnail_url: impl Into<String>) -> Self {
+        self.field("thumbnail_url", thumbnail_url.into())
+    }
+
+    /// Send `text` instead of the media of the result.
+    pub fn text(self, text: impl Into<String>) -> Self {
+        self.field(
+            "input_message_content",
+            json!({ "message_text": text.into() }),
+        )
+    }
+
+    pub fn keyboard(self, keyboard: InlineKeyboard) -> Self {
+        self.field("reply_markup", keyboard.markup())
+    }
+
+    /// Set a raw field of the result.
+    pub fn field(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.0.insert(key.to_string(), value.into());
+        self
+    }
+
+    pub fn id(&self) -> &str {
+        self.0["id"].as_str().unwrap_or_default()
+    }
+}
+
+/// Options of `TelegramBot::answer_inline_query`.
+#[derive(Debug, Clone, Default)]
+pub struct InlineAnswer {
+    /// Seconds Telegram may cache the results for, 300 if `None`.
+    pub cache_time: Option<i64>,
+    /// Cache the results for the querying user only.
+    pub is_personal: bool,
+    /// Sent back as `InlineQueryEvent::offset` when the user scrolls past the results.
+    /// `None` when there are no more results.
+    pub next_offset: Option<String>,
+    /// A button shown above the results.
+    pub button: Option<InlineQueryResultsButton>,
+}
+
+/// Take the page of `items` starting at `offset`, an `InlineQueryEvent::offset`,
+/// along with the `next_offset` of the following page. `page_size` is kept within 1 and
+/// `MAX_INLINE_RESULTS`.
+pub fn paginate<T>(items: Vec<T>, offset: &str, page_size: usize) -> (Vec<T>, Option<String>) {
+    let start: usize = offset.parse().unwrap_or(0);
+    let end = start.saturating_add(page_size.clamp(1, MAX_INLINE_RESULTS));
+    let next_offset = (items.len() > end).then(|| end.to_string());
+    let page = items.into_iter().skip(start).take(end - start).collect();
+    (page, next_offset)
+}
+
+impl TelegramBot {
+    /// Answer an inline query, remembering the result ids for `chosen_inline_query`.
+    pub async fn answer_inline_query(
+        &self,
+        query: &InlineQueryEvent,
+        results: Vec<InlineResult>,
+        answer: InlineAnswer,
+    ) -> Result<()> {
+        let result_ids = results
+            .iter()
+            .map(|result| result.id().to_string())
+            .collect();
+        let mut payload = json!({
+            "inline_query_id": query.id,
+            "results": results.into_iter().map(|result| result.0).collect::<Vec<_>>(),
+            "is_personal": answer.is_personal,
+        });
+        if let Some(cache_time) = answer.cache_time {
+            payload["cache_time"] = cache_time.into();
+        }
+        if let Some(next_offset) = answer.next_offset {
+            payload["next_offset"] = next_offset.into();
+        }
+        if let Some(button) = answer.button {
+            payload["button"] = serde_json::to_value(button)?;
+        }
+        call_api_json::<bool, _>(&self.bot, "answerInlineQuery", &payload).await?;
+        self.inline_queries
+            .insert(query.raw.from.id, &query.query, &query.id, result_ids);
+        Ok(())
+    }
+
+    /// Id of the inline query the chosen result was an answer to,
+    /// if it was answered by this bot since it started.
+    pub fn chosen_inline_query(&self, result: &ChosenInlineResultEvent) -> Option<String> {
+        self.inline_queries
+            .get(result.raw.from.id, &result.query, &result.result_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paginate_pages_through_items() {
+        let items = (0..5).collect::<Vec<_>>();
+        assert_eq!(
+            paginate(items.clone(), "", 2),
+            (vec![0, 1], Some("2".to_string()))
+        );
+        assert_eq!(
+            paginate(items.clone(), "2", 2),
+            (vec![2, 3], Some("4".to_string()))
+        );
+        assert_eq!(paginate(items.clone(), "4", 2), (vec![4], None));
+        assert_eq!(paginate(items, "9", 2), (vec![], None));
+    }
+
+    #[test]
+    fn paginate_caps_page_size() {
+        let items = (0..60).collect::<Vec<_>>();
+        let (page, next_offset) = paginate(items, "", 100);
+        assert_eq!(page.len(), MAX_INLINE_RESULTS);
+        assert_eq!(next_offset.as_deref(), Some("50"));
+    }
+
+    #[test]
+    fn paginate_always_moves_forward() {
+        let items = (0..3).collect::<Vec<_>>();
+        assert_eq!(
+            paginate(items.clone(), "", 0),
+            (vec![0], Some("1".to_string()))
+        );
+        let offset = usize::MAX.to_string();
+        assert_eq!(paginate(items, &offset, 2), (vec![], None));
+    }
+}
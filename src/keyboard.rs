@@ -0,0 +1,195 @@
+use oxidebot::source::message::MessageSegment;
+use serde_json::{json, Value};
+
+/// `CustomValue` type of an inline keyboard, its data is an `InlineKeyboardMarkup`.
+pub const INLINE_KEYBOARD: &str = "inline_keyboard";
+/// `CustomValue` type of a custom reply keyboard, its data is a `ReplyKeyboardMarkup`.
+pub const REPLY_KEYBOARD: &str = "reply_keyboard";
+/// `CustomValue` type removing the reply keyboard, its data is a `ReplyKeyboardRemove`.
+pub const REMOVE_KEYBOARD: &str = "remove_keyboard";
+/// `CustomValue` type asking the client to reply, its data is a `ForceReply`.
+pub const FORCE_REPLY: &str = "force_reply";
+
+/// Builder of an inline keyboard segment, attached to the last message `send_message` sends
+/// or replacing the keyboard in `edit_messagee`.
+/// Built as json since the library's `InlineKeyboardButton` can't express web app buttons.
+#[derive(Debug, Clone, Default)]
+pub struct InlineKeyboard {
+    rows: Vec<Vec<Value>>,
+}
+
+impl InlineKeyboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a new row, buttons are added to the last row.
+    pub fn row(mut self) -> Self {
+        self.rows.push(Vec::new());
+        self
+    }
+
+    /// A button sending `data` back in a callback query.
+    pub fn callback(self, text: impl Into<String>, data: impl Into<String>) -> Self {
+        self.button(json!({ "text": text.into(), "callback_data": data.into() }))
+    }
+
+    pub fn url(self, text: impl Into<String>, url: impl Into<String>) -> Self {
+        self.button(json!({ "text": text.into(), "url": url.into() }))
+    }
+
+    /// A button letting the user pick a chat to start an inline query with `query` in.
+    pub fn switch_inline(self, text: impl Into<String>, query: impl Into<String>) -> Self {
+        self.button(json!({ "text": text.into(), "switch_inline_query": query.into() }))
+    }
+
+    /// A button starting an inline query with `query` in the current chat.
+    pub fn switch_inline_current_chat(
+        self,
+        text: impl Into<String>,
+        query: impl Into<String>,
+    ) -> Self {
+        self.button(json!({
+            "text": text.into(),
+            "switch_inline_query_current_chat": query.into(),
+        }))
+    }
+
+    pub fn web_app(self, text: impl Into<String>, url: impl Into<String>) -> Self {
+        self.button(json!({ "text": text.into(), "web_app": { "url": url.into() } }))
+    }
+
+    /// Add a raw `InlineKeyboardButton`.
+    pub fn button(mut self, button: Value) -> Self {
+        match self.rows.last_mut() {
+            Some(row) => row.push(button),
+            None => self.rows.push(vec![button]),
+        }
+        self
+    }
+
+    /// The `InlineKeyboardMarkup`, for inline query results.
+    pub fn markup(self) -> Value {
+        json!({ "inline_keyboard": self.rows })
+    }
+
+    pub fn build(self) -> MessageSegment {
+        MessageSegment::CustomValue {
+            r#type: INLINE_KEYBOARD.to_string(),
+            data: self.markup(),
+        }
+    }
+}
+
+/// Builder of a custom reply keyboard segment, shown to the user instead of the letter keyboard.
+/// Only `send_message` can attach it, the buttons send their text as a message.
+#[derive(Debug, Clone, Default)]
+pub struct ReplyKeyboard {
+    rows: Vec<Vec<Value>>,
+    options: serde_json::Map<String, Value>,
+}
+
+impl ReplyKeyboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a new row, buttons are added to the last row.
+    pub fn row(mut self) -> Self {
+        self.rows.push(Vec::new());
+        self
+    }
+
+    pub fn text(self, text: impl Into<String>) -> Self {
+        self.button(json!({ "text": text.into() }))
+    }
+
+    /// A button sending the user's phone number, received as a `"contact"` segment.
+    pub fn request_contact(self, text: impl Into<String>) -> Self {
+        self.button(json!({ "text": text.into(), "request_contact": true }))
+    }
+
+    /// A button sending the user's location, received as a `Location` segment.
+    pub fn request_location(self, text: impl Into<String>) -> Self {
+        self.button(json!({ "text": text.into(), "request_location": true }))
+    }
+
+    pub fn web_app(self, text: impl Into<String>, url: impl Into<String>) -> Self {
+        self.button(json!({ "text": text.into(), "web_app": { "url": url.into() } }))
+    }
+
+    /// Add a raw `KeyboardButton`.
+    pub fn button(mut self, button: Value) -> Self {
+        match self.rows.last_mut() {
+            Some(row) => row.push(button),
+            None => self.rows.push(vec![button]),
+        }
+        self
+    }
+
+    /// Keep the keyboard shown when the regular keyboard is hidden.
+    pub fn persistent(self) -> Self {
+        self.option("is_persistent", true)
+    }
+
+    /// Fit the keyboard height to its buttons.
+    pub fn resize(self) -> Self {
+        self.option("resize_keyboard", true)
+    }
+
+    /// Hide the keyboard once a button is pressed.
+    pub fn one_time(self) -> Self {
+        self.option("one_time_keyboard", true)
+    }
+
+    pub fn placeholder(self, placeholder: impl Into<String>) -> Self {
+        self.option("input_field_placeholder", placeholder.into())
+    }
+
+    /// Only show the keyboard to the mentioned users or the sender of the replied message.
+    pub fn selective(self) -> Self {
+        self.option("selective", true)
+    }
+
+    fn option(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.options.insert(key.to_string(), value.into());
+        self
+    }
+
+    pub fn build(mut self) -> MessageSegment {
+        self.options
+            .insert("keyboard".to_string(), json!(self.rows));
+        MessageSegment::CustomValue {
+            r#type: REPLY_KEYBOARD.to_string(),
+            data: Value::Object(self.options),
+        }
+    }
+}
+
+/// A segment removing the custom reply keyboard, for the mentioned users only if `selective`.
+pub fn remove_keyboard(selective: bool) -> MessageSegment {
+    MessageSegment::CustomValue {
+        r#type: REMOVE_KEYBOARD.to_string(),
+        data: json!({ "remove_keyboard": true, "selective": selective }),
+    }
+}
+
+/// A segment making the client show a reply interface, as if the user chose to reply.
+pub fn force_reply(placeholder: Option<String>, selective: bool) -> MessageSegment {
+    let mut data = json!({ "force_reply": true, "selective": selective });
+    if let Some(placeholder) = placeholder {
+        data["input_field_placeholder"] = placeholder.into();
+    }
+    MessageSegment::CustomValue {
+        r#type: FORCE_REPLY.to_string(),
+        data,
+    }
+}
+
+/// Whether a `CustomValue` of `type` is a reply markup, whose data is sent as is.
+pub fn is_reply_markup(r#type: &str) -> bool {
+    matches!(
+        r#type,
+        INLINE_KEYBOARD | REPLY_KEYBOARD | REMOVE_KEYBOARD | FORCE_REPLY
+    )
+}
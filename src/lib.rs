@@ -1,6 +1,19 @@
 pub mod bot;
+pub mod business;
+pub mod callback;
+pub mod command;
+pub mod error;
 pub mod event;
+pub mod extension;
+pub mod inline;
+pub mod keyboard;
+pub mod payment;
+pub mod poll;
+pub mod polling;
+pub mod request;
 pub mod segment;
+pub mod store;
 pub mod utils;
+pub mod webhook;
 pub mod api;
 pub const SERVER: &'static str = "telegram";
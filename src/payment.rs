@@ -0,0 +1,237 @@
+use anyhow::Result;
+use oxidebot::{event::any::AnyEventDataTrait, source::user::User};
+use serde::Serialize;
+use telegram_bot_api_rs::{
+    available_types::{Message, RefundedPayment, SuccessfulPayment},
+    payments::{
+        payload::{
+            AnswerPreCheckoutQueryPayload, AnswerShippingQueryPayload, RefundStarPaymentPayload,
+        },
+        types::{LabeledPrice, ShippingOption},
+    },
+};
+
+use crate::{
+    bot::TelegramBot,
+    request::call_api_json,
+    utils::{self, parse_user, split_target},
+};
+
+/// Currency of payments in Telegram Stars.
+pub const STARS: &str = "XTR";
+
+/// An invoice to send with `TelegramBot::send_invoice` or `TelegramBot::create_invoice_link`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TelegramInvoice {
+    pub title: String,
+    pub description: String,
+    /// Returned in the shipping, pre-checkout and payment updates, not shown to the user.
+    pub payload: String,
+    /// `None` for payments in Telegram Stars.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider_token: Option<String>,
+    pub currency: String,
+    /// Amounts in the smallest units of the currency.
+    pub prices: Vec<LabeledPrice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tip_amount: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggested_tip_amounts: Option<Vec<i64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub photo_url: Option<String>,
+    pub need_name: bool,
+    pub need_phone_number: bool,
+    pub need_email: bool,
+    pub need_shipping_address: bool,
+    /// The final price depends on the shipping address, the bot receives shipping queries.
+    pub is_flexible: bool,
+}
+
+impl TelegramInvoice {
+    pub fn new(
+        title: impl Into<String>,
+        description: impl Into<String>,
+        payload: impl Into<String>,
+        provider_token: impl Into<String>,
+        currency: impl Into<String>,
+        prices: Vec<LabeledPrice>,
+    ) -> Self {
+        Self {
+            title: title.into(),
+            description: description.into(),
+            payload: payload.into(),
+            provider_token: Some(provider_token.into()),
+            currency: currency.into(),
+            prices,
+            ..Default::default()
+        }
+    }
+
+    /// An invoice of `amount` Telegram Stars.
+    pub fn stars(
+        title: impl Into<String>,
+        description: impl Into<String>,
+        payload: impl Into<String>,
+        amount: i64,
+    ) -> Self {
+        let title = title.into();
+        Self {
+            prices: vec![LabeledPrice {
+                label: title.clone(),
+                amount,
+            }],
+            title,
+            description: description.into(),
+            payload: payload.into(),
+            currency: STARS.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+/// A payment went through, sent as a `TelegramEventKind::SuccessfulPayment` event
+/// along with the message event. Deliver the goods once received.
+#[derive(Debug, Clone)]
+pub struct SuccessfulPaymentEvent {
+    /// The service message, as `{chat_id}_{message_id}`.
+    pub message_id: String,
+    pub sender: Option<User>,
+    pub currency: String,
+    pub total_amount: i64,
+    pub invoice_payload: String,
+    /// Needed to refund a payment in Telegram Stars.
+    pub telegram_payment_charge_id: String,
+    pub raw: SuccessfulPayment,
+}
+
+impl SuccessfulPaymentEvent {
+    pub fn new(message: &Message, payment: SuccessfulPayment) -> Self {
+        Self {
+            message_id: utils::message_id(message),
+            sender: message.from.clone().map(parse_user),
+            currency: payment.currency.clone(),
+            total_amount: payment.total_amount,
+            invoice_payload: payment.invoice_payload.clone(),
+            telegram_payment_charge_id: payment.telegram_payment_charge_id.clone(),
+            raw: payment,
+        }
+    }
+}
+
+impl AnyEventDataTrait for SuccessfulPaymentEvent {
+    fn clone_box(&self) -> Box<dyn AnyEventDataTrait> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+/// A payment was refunded, sent as a `TelegramEventKind::RefundedPayment` event.
+#[derive(Debug, Clone)]
+pub struct RefundedPaymentEvent {
+    pub message_id: String,
+    pub sender: Option<User>,
+    pub currency: String,
+    pub total_amount: i64,
+    pub invoice_payload: String,
+    pub telegram_payment_charge_id: String,
+    pub raw: RefundedPayment,
+}
+
+impl RefundedPaymentEvent {
+    pub fn new(message: &Message, payment: RefundedPayment) -> Self {
+        Self {
+            message_id: utils::message_id(message),
+            sender: message.from.clone().map(parse_user),
+            currency: payment.currency.clone(),
+            total_amount: payment.total_amount,
+            invoice_payload: payment.invoice_payload.clone(),
+            telegram_payment_charge_id: payment.telegram_payment_charge_id.clone(),
+            raw: payment,
+        }
+    }
+}
+
+impl AnyEventDataTrait for RefundedPaymentEvent {
+    fn clone_box(&self) -> Box<dyn AnyEventDataTrait> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+impl TelegramBot {
+    /// Send an invoice, `chat_id` may target a forum topic as `{chat_id}_{message_thread_id}`.
+    /// Returns the message id.
+    pub async fn send_invoice(&self, chat_id: String, invoice: TelegramInvoice) -> Result<String> {
+        let (chat_id, thread_id) = split_target(chat_id)?;
+        let mut payload = serde_json::to_value(invoice)?;
+        payload["chat_id"] = chat_id.into();
+        if let Some(thread_id) = thread_id {
+            payload["message_thread_id"] = thread_id.into();
+        }
+        let message: Message = call_api_json(&self.bot, "sendInvoice", &payload).await?;
+        self.message_store.insert(&message);
+        Ok(utils::message_id(&message))
+    }
+
+    /// Create a link to pay the invoice from anywhere.
+    pub async fn create_invoice_link(&self, invoice: TelegramInvoice) -> Result<String> {
+        call_api_json(&self.bot, "createInvoiceLink", &invoice).await
+    }
+
+    /// Answer a shipping query with the available options,
+    /// or refuse delivery to the address with `error_message`.
+    pub async fn answer_shipping_query(
+        &self,
+        query_id: String,
+        options: Vec<ShippingOption>,
+        error_message: Option<String>,
+    ) -> Result<()> {
+        self.bot
+            .answer_shipping_query(&AnswerShippingQueryPayload {
+                shipping_query_id: query_id,
+                ok: error_message.is_none(),
+                shipping_options: error_message.is_none().then_some(options),
+                error_message,
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Confirm a pre-checkout query, or cancel the payment with `error_message`.
+    /// Must be answered within 10 seconds.
+    pub async fn answer_pre_checkout_query(
+        &self,
+        query_id: String,
+        error_message: Option<String>,
+    ) -> Result<()> {
+        self.bot
+            .answer_pre_checkout_query(&AnswerPreCheckoutQueryPayload {
+                pre_checkout_query_id: query_id,
+                ok: error_message.is_none(),
+                error_message,
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Refund a payment in Telegram Stars.
+    pub async fn refund_star_payment(
+        &self,
+        user_id: String,
+        telegram_payment_charge_id: String,
+    ) -> Result<()> {
+        self.bot
+            .refund_star_payment(&RefundStarPaymentPayload {
+                user_id: user_id.parse()?,
+                telegram_payment_charge_id,
+            })
+            .await?;
+        Ok(())
+    }
+}
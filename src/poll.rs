@@ -0,0 +1,108 @@
+use anyhow::Result;
+use telegram_bot_api_rs::{
+    available_methods::payload::SendPollPayload,
+    available_types::{InputPollOption, Message, Poll},
+    updateing_messages::payload::StopPollPayload,
+};
+
+use crate::{
+    bot::TelegramBot,
+    request::call_api_json,
+    store::SentPoll,
+    utils::{self, split_id, split_target},
+};
+
+/// A poll to send with `TelegramBot::send_poll`.
+#[derive(Debug, Clone, Default)]
+pub struct TelegramPoll {
+    pub question: String,
+    pub options: Vec<String>,
+    pub is_anonymous: bool,
+    /// Ignored for quizzes.
+    pub allows_multiple_answers: bool,
+    /// Index of the correct option, which makes the poll a quiz.
+    pub correct_option_id: Option<i64>,
+    /// Shown when a quiz is answered wrong.
+    pub explanation: Option<String>,
+    /// Seconds the poll stays open after being sent, 5 to 600.
+    pub open_period: Option<i64>,
+}
+
+impl TelegramPoll {
+    /// An anonymous regular poll.
+    pub fn new(question: impl Into<String>, options: Vec<String>) -> Self {
+        Self {
+            question: question.into(),
+            options,
+            is_anonymous: true,
+            ..Default::default()
+        }
+    }
+
+    /// An anonymous quiz, `correct_option_id` being the index of the right answer.
+    pub fn quiz(question: impl Into<String>, options: Vec<String>, correct_option_id: i64) -> Self {
+        Self {
+            correct_option_id: Some(correct_option_id),
+            ..Self::new(question, options)
+        }
+    }
+}
+
+impl TelegramBot {
+    /// Send a poll and register it, returning the message id.
+    /// `chat_id` may target a forum topic as `{chat_id}_{message_thread_id}`.
+    /// Non anonymous polls report votes as `PollAnswer` updates, see `sent_poll`.
+    pub async fn send_poll(&self, chat_id: String, poll: TelegramPoll) -> Result<String> {
+        let (chat_id, thread_id) = split_target(chat_id)?;
+        let mut payload = serde_json::to_value(SendPollPayload {
+            chat_id,
+            message_thread_id: thread_id,
+            question: poll.question,
+            options: poll
+                .options
+                .into_iter()
+                .map(|text| InputPollOption {
+                    text,
+                    ..Default::default()
+                })
+                .collect(),
+            is_anonymous: Some(poll.is_anonymous),
+            allows_multiple_answers: Some(poll.allows_multiple_answers),
+            correct_option_id: poll.correct_option_id,
+            explanation: poll.explanation,
+            open_period: poll.open_period,
+            ..Default::default()
+        })?;
+        // the library serializes the poll type as `type_`
+        if poll.correct_option_id.is_some() {
+            payload["type"] = "quiz".into();
+        }
+        let message: Message = call_api_json(&self.bot, "sendPoll", &payload).await?;
+        self.message_store.insert(&message);
+        let message_id = utils::message_id(&message);
+        if let Some(poll) = message.poll {
+            self.poll_registry.insert(message_id.clone(), poll);
+        }
+        Ok(message_id)
+    }
+
+    /// Close a poll sent by the bot, returning its final state.
+    pub async fn stop_poll(&self, message_id: String) -> Result<Poll> {
+        let (chat_id, message_id) = split_id(message_id)?;
+        let poll = self
+            .bot
+            .stop_poll(&StopPollPayload {
+                chat_id,
+                message_id: message_id.parse()?,
+                ..Default::default()
+            })
+            .await?;
+        self.poll_registry.remove(&poll.id);
+        Ok(poll)
+    }
+
+    /// The poll a `PollAnswer` update with `poll_id` answers, if the bot sent it and it's still open.
+    pub fn sent_poll(&self, poll_id: &str) -> Option<SentPoll> {
+        self.poll_registry.get(poll_id)
+    }
+}
@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::{bot::TelegramBot, event::parse_raw_update, request::call_api_json};
+
+impl TelegramBot {
+    /// Long poll `getUpdates` in place of `Bot::start_get_updates`, which deserializes a whole
+    /// batch at once: an update the library can't parse fails the batch, is refetched forever
+    /// and stalls every later update. Here updates go through `parse_raw_update` one by one,
+    /// and the offset moves past unparsable ones, which are logged and skipped.
+    pub(crate) fn start_polling(&self) {
+        let bot = self.bot.clone();
+        let mut config = self.config.clone();
+        tokio::spawn(async move {
+            loop {
+                let updates =
+                    match call_api_json::<Vec<Value>, _>(&bot, "getUpdates", &config).await {
+                        Ok(updates) => updates,
+                        Err(e) => {
+                            tracing::error!("Error while getting updates: {:?}", e);
+                            tokio::time::sleep(Duration::from_secs(1)).await;
+                            continue;
+                        }
+                    };
+                for update in updates {
+                    if let Some(update_id) = update.get("update_id").and_then(Value::as_i64) {
+                        config.offset = Some(update_id + 1);
+                    }
+                    match parse_raw_update(update) {
+                        Ok(update) => {
+                            if let Err(e) = bot.sender.send(update.data) {
+                                tracing::error!("Error while sending update: {:?}", e);
+                            }
+                        }
+                        Err(e) => tracing::error!("Error while parsing update: {:?}", e),
+                    }
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,284 @@
+use anyhow::Result;
+use base64::Engine as _;
+use oxidebot::source::message::File;
+use reqwest::multipart::{Form, Part};
+use serde::{de::DeserializeOwned, Serialize};
+use telegram_bot_api_rs::{
+    available_methods::payload::GetFilePayload,
+    available_types::{InputMedia, Message, ReplyParameters},
+    bot::{Bot, CallApiResp},
+};
+
+use crate::utils::local_path;
+
+// telegram_bot_api_rs keeps its request helpers private, so methods it lacks
+// (or implements incorrectly) are called through these.
+
+pub(crate) async fn call_api_json<D: DeserializeOwned, S: Serialize>(
+    bot: &Bot,
+    method: &str,
+    payload: &S,
+) -> Result<D> {
+    let resp = bot
+        .client
+        .post(format_url(bot, method))
+        .json(payload)
+        .send()
+        .await?
+        .json::<CallApiResp>()
+        .await?;
+    parse_resp(resp)
+}
+
+/// Call `method` with `payload` and a raw `reply_markup`, which the library payloads can't all express.
+pub(crate) async fn call_api_with_markup<D: DeserializeOwned, S: Serialize>(
+    bot: &Bot,
+    method: &str,
+    payload: &S,
+    reply_markup: Option<&serde_json::Value>,
+) -> Result<D> {
+    let mut payload = serde_json::to_value(payload)?;
+    if let (Some(reply_markup), Some(fields)) = (reply_markup, payload.as_object_mut()) {
+        fields.insert("reply_markup".to_string(), reply_markup.clone());
+    }
+    call_api_json(bot, method, &payload).await
+}
+
+pub(crate) async fn call_api_multipart<D: DeserializeOwned>(
+    bot: &Bot,
+    method: &str,
+    form: Form,
+) -> Result<D> {
+    let resp = bot
+        .client
+        .post(format_url(bot, method))
+        .multipart(form)
+        .send()
+        .await?
+        .json::<CallApiResp>()
+        .await?;
+    parse_resp(resp)
+}
+
+fn format_url(bot: &Bot, method: &str) -> String {
+    format!("https://api.telegram.org/bot{}/{}", bot.token, method)
+}
+
+fn parse_resp<D: DeserializeOwned>(resp: CallApiResp) -> Result<D> {
+    match resp.ok {
+        true => match resp.result {
+            Some(result) => Ok(serde_json::from_value(result)?),
+            None => Err(anyhow::anyhow!("Failed to call api: No result")),
+        },
+        false => Err(anyhow::anyhow!(
+            "Failed to call api: {}",
+            resp.description.unwrap_or("No description".to_string())
+        )),
+    }
+}
+
+/// Read the content of a `File` from its base64, uri or telegram file id.
+/// The uri is either an http(s) url or a local path, see `utils::local_path`.
+pub(crate) async fn read_file(bot: &Bot, file: &File) -> Result<Vec<u8>> {
+    if let Some(base64) = &file.base64 {
+        return Ok(base64::engine::general_purpose::STANDARD.decode(base64)?);
+    }
+    if let Some(uri) = &file.uri {
+        return match local_path(uri)? {
+            Some(path) => Ok(tokio::fs::read(path).await?),
+            None => Ok(bot
+                .client
+                .get(uri.to_string())
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await?
+                .to_vec()),
+        };
+    }
+    if let Some(id) = &file.id {
+        let file = bot
+            .get_file(&GetFilePayload {
+                file_id: id.clone(),
+            })
+            .await?;
+        if file.file_path.is_none() {
+            return Err(anyhow::anyhow!("File path not found"));
+        }
+        return Ok(bot
+            .client
+            .get(file.get_file_path(&bot.token))
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?
+            .to_vec());
+    }
+    Err(anyhow::anyhow!("File has no base64, uri or id"))
+}
+
+/// Build a multipart part holding the content of a `File`.
+pub(crate) async fn file_to_part(bot: &Bot, file: &File) -> Result<Part> {
+    let mut part = Part::bytes(read_file(bot, file).await?).file_name(if file.name.is_empty() {
+        "file".to_string()
+    } else {
+        file.name.clone()
+    });
+    if let Some(mime) = &file.mime {
+        part = part.mime_str(mime.as_ref())?;
+    }
+    Ok(part)
+}
+
+/// Where `send_media` sends to.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MediaTarget {
+    pub chat_id: String,
+    pub thread_id: Option<i64>,
+    pub business_connection_id: Option<String>,
+}
+
+/// Send media through multipart so that `attach://` references in `media` can be uploaded from `uploads`.
+/// A single media is sent with its own method, as `sendMediaGroup` needs at least two.
+pub(crate) async fn send_media(
+    bot: &Bot,
+    target: MediaTarget,
+    mut media: Vec<InputMedia>,
+    uploads: Vec<(String, File)>,
+    reply: Option<ReplyParameters>,
+    reply_markup: Option<&serde_json::Value>,
+) -> Result<Vec<Message>> {
+    let mut form = target_form(target, reply)?;
+    if media.len() != 1 {
+        if reply_markup.is_some() {
+            tracing::warn!("Reply markup can't be attached to a media group");
+        }
+        form = form.text("media", serde_json::to_string(&media)?);
+        for (name, file) in &uploads {
+            form = form.part(name.clone(), file_to_part(bot, file).await?);
+        }
+        return call_api_multipart(bot, "sendMediaGroup", form).await;
+    }
+    let media = media.pop().unwrap();
+    let (method, field) = match &media {
+        InputMedia::Photo { .. } => ("sendPhoto", "photo"),
+        InputMedia::Video { .. } => ("sendVideo", "video"),
+        InputMedia::Animation { .. } => ("sendAnimation", "animation"),
+        InputMedia::Audio { .. } => ("sendAudio", "audio"),
+        InputMedia::Document { .. } => ("sendDocument", "document"),
+    };
+    let serde_json::Value::Object(fields) = serde_json::to_value(&media)? else {
+        return Err(anyhow::anyhow!("Failed to serialize media"));
+    };
+    for (key, value) in fields {
+        form = match (key.as_str(), value) {
+            ("type", _) => form,
+            ("media", serde_json::Value::String(media)) => match media.strip_prefix("attach://") {
+                Some(name) => {
+                    let (_, file) = uploads
+                        .iter()
+                        .find(|(n, _)| n == name)
+                        .ok_or(anyhow::anyhow!("Upload {} not found", name))?;
+                    form.part(field, file_to_part(bot, file).await?)
+                }
+                None => form.text(field, media),
+            },
+            (_, serde_json::Value::String(value)) => form.text(key, value),
+            (_, value) => form.text(key, value.to_string()),
+        };
+    }
+    if let Some(reply_markup) = reply_markup {
+        form = form.text("reply_markup", reply_markup.to_string());
+    }
+    Ok(vec![call_api_multipart(bot, method, form).await?])
+}
+
+/// Send a round video message. Telegram doesn't take video notes by url,
+/// so anything but a telegram file id is uploaded.
+pub(crate) async fn send_video_note(
+    bot: &Bot,
+    target: MediaTarget,
+    file: &File,
+    duration: Option<i64>,
+    reply: Option<ReplyParameters>,
+    reply_markup: Option<&serde_json::Value>,
+) -> Result<Message> {
+    let mut form = target_form(target, reply)?;
+    form = match &file.id {
+        Some(id) => form.text("video_note", id.clone()),
+        None => form.part("video_note", file_to_part(bot, file).await?),
+    };
+    if let Some(duration) = duration {
+        form = form.text("duration", duration.to_string());
+    }
+    if let Some(reply_markup) = reply_markup {
+        form = form.text("reply_markup", reply_markup.to_string());
+    }
+    call_api_multipart(bot, "sendVideoNote", form).await
+}
+
+fn target_form(target: MediaTarget, reply: Option<ReplyParameters>) -> Result<Form> {
+    let mut form = Form::new().text("chat_id", target.chat_id);
+    if let Some(thread_id) = target.thread_id {
+        form = form.text("message_thread_id", thread_id.to_string());
+    }
+    if let Some(business_connection_id) = target.business_connection_id {
+        form = form.text("business_connection_id", business_connection_id);
+    }
+    if let Some(reply) = reply {
+        form = form.text("reply_parameters", serde_json::to_string(&reply)?);
+    }
+    Ok(form)
+}
+
+/// The message returned by an edit method, `None` for inline messages for which it returns `true`.
+pub(crate) fn edited_message(result: serde_json::Value) -> Result<Option<Message>> {
+    match result {
+        serde_json::Value::Bool(true) => Ok(None),
+        result => Ok(Some(serde_json::from_value(result)?)),
+    }
+}
+
+/// Replace the media of a message through multipart, uploading `attach://` references from `uploads`.
+/// Returns the result of `editMessageMedia`, see `edited_message`.
+pub(crate) async fn edit_media(
+    bot: &Bot,
+    chat_id: String,
+    message_id: i64,
+    media: InputMedia,
+    uploads: Vec<(String, File)>,
+    reply_markup: Option<&serde_json::Value>,
+) -> Result<serde_json::Value> {
+    let mut form = Form::new()
+        .text("chat_id", chat_id)
+        .text("message_id", message_id.to_string())
+        .text("media", serde_json::to_string(&media)?);
+    if let Some(reply_markup) = reply_markup {
+        form = form.text("reply_markup", reply_markup.to_string());
+    }
+    for (name, file) in &uploads {
+        form = form.part(name.clone(), file_to_part(bot, file).await?);
+    }
+    call_api_multipart(bot, "editMessageMedia", form).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edited_message_is_a_message_or_true() {
+        let edited = edited_message(serde_json::json!({
+            "message_id": 7,
+            "date": 0,
+            "chat": { "id": -1001, "type": "supergroup" },
+            "text": "edited",
+        }))
+        .unwrap();
+        assert_eq!(edited.map(|message| message.message_id), Some(7));
+        assert!(edited_message(serde_json::json!(true)).unwrap().is_none());
+        assert!(edited_message(serde_json::json!({ "text": "edited" })).is_err());
+    }
+}
@@ -1,21 +1,50 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
 use oxidebot::source::message::{File, Message, MessageSegment};
-use telegram_bot_api_rs::available_types::{
-    self, InputMedia, Location, MessageEntity, PhotoSize, ReactionType, ReplyParameters, User,
-    Venue,
+use serde::Serialize;
+use serde_json::json;
+use telegram_bot_api_rs::{
+    available_methods::payload::{ChatIdPayload, GetChatMemberPayload},
+    available_types::{
+        self, ChatMember, InputMedia, Location, MessageEntity, PhotoSize, ReactionType,
+        ReplyParameters, User, Venue,
+    },
+};
+
+use crate::{
+    bot::TelegramBot,
+    error::TelegramError,
+    keyboard::is_reply_markup,
+    store::UserCache,
+    utils::{self, chat_member_user, split_id, utf16_len},
 };
+/// Mime of a parsed video note.
+pub const VIDEO_NOTE_MIME: &str = "video/mp4; round=true";
 
-use crate::utils::split_id;
-pub fn parse_message(message: available_types::Message) -> Message {
+pub fn parse_message(message: available_types::Message, users: &UserCache) -> Message {
+    let id = utils::message_id(&message);
     let mut segments = Vec::new();
-    if let Some(text) = message.text {
-        segments.push(MessageSegment::text(text));
+    for user in message
+        .from
+        .iter()
+        .chain(message.via_bot.iter())
+        .chain(message.new_chat_members.iter().flatten())
+        .chain(
+            message
+                .reply_to_message
+                .iter()
+                .filter_map(|m| m.from.as_ref()),
+        )
+    {
+        users.insert(user);
     }
-    if let Some(entities) = message.entities {
-        for entity in entities.into_iter() {
-            if let Some(segment) = parse_message_entity(entity) {
-                segments.push(segment);
-            }
-        }
+    if let Some(text) = message.text {
+        segments.extend(parse_text(
+            text,
+            message.entities.unwrap_or_default(),
+            users,
+        ));
     }
     if let Some(photo) = message.photo {
         let photo = photo.into_iter().max_by_key(|p| p.width * p.height);
@@ -35,33 +64,33 @@ pub fn parse_message(message: available_types::Message) -> Message {
     if let Some(document) = message.document {
         segments.push(parse_document(document))
     }
-    if let Some(_) = message.paid_media {
-        tracing::warn!("Paid media not supported");
+    if let Some(paid_media) = message.paid_media {
+        segments.push(custom_value("paid_media", paid_media));
     }
     if let Some(sticker) = message.sticker {
         segments.push(parse_sticker(sticker))
     }
-    if let Some(_) = message.story {
-        tracing::warn!("Story not supported");
+    if let Some(story) = message.story {
+        segments.push(custom_value(
+            "story",
+            json!({ "chat_id": story.chat.id, "id": story.id }),
+        ));
     }
-    if message.video_note.is_some() {
-        tracing::warn!("Video note not supported");
+    if let Some(video_note) = message.video_note {
+        segments.push(parse_video_note(video_note))
     }
     if let Some(voice) = message.voice {
         segments.push(parse_voice(voice))
     }
     if let Some(caption) = message.caption {
-        segments.push(MessageSegment::text(caption));
+        segments.extend(parse_text(
+            caption,
+            message.caption_entities.unwrap_or_default(),
+            users,
+        ));
     }
-    if let Some(caption_entities) = message.caption_entities {
-        for entity in caption_entities.into_iter() {
-            if let Some(segment) = parse_message_entity(entity) {
-                segments.push(segment);
-            }
-        }
-    }
-    if let Some(_) = message.contact {
-        tracing::warn!("Contact not supported");
+    if let Some(contact) = message.contact {
+        segments.push(parse_contact(contact));
     }
     if let Some(venue) = message.venue {
         segments.push(parse_venue(venue))
@@ -69,10 +98,25 @@ pub fn parse_message(message: available_types::Message) -> Message {
     if let Some(location) = message.location {
         segments.push(parse_location(location))
     }
-    Message {
-        id: format!("{}_{}", message.chat.id, message.message_id),
-        segments,
+    if let Some(dice) = message.dice {
+        segments.push(custom_value("dice", dice))
+    }
+    if let Some(poll) = message.poll {
+        segments.push(custom_value("poll", poll))
+    }
+    if let Some(game) = message.game {
+        segments.push(custom_value("game", game))
+    }
+    if let Some(invoice) = message.invoice {
+        segments.push(custom_value("invoice", invoice))
+    }
+    if let Some(payment) = message.successful_payment {
+        segments.push(custom_value("successful_payment", payment))
+    }
+    if let Some(payment) = message.refunded_payment {
+        segments.push(custom_value("refunded_payment", payment))
     }
+    Message { id, segments }
 }
 pub fn parse_photo(photo: PhotoSize) -> MessageSegment {
     MessageSegment::Image {
@@ -131,6 +175,31 @@ pub fn parse_video(video: telegram_bot_api_rs::available_types::Video) -> Messag
     }
 }
 
+/// A round video message, a `Video` whose mime carries `round=true`, see `is_video_note`.
+pub fn parse_video_note(
+    video_note: telegram_bot_api_rs::available_types::VideoNote,
+) -> MessageSegment {
+    MessageSegment::Video {
+        file: Some(File {
+            id: Some(video_note.file_id.clone()),
+            name: video_note.file_id,
+            uri: None,
+            base64: None,
+            mime: VIDEO_NOTE_MIME.parse().ok(),
+            size: video_note.file_size.map(|size| size as u64),
+        }),
+        length: Some(video_note.duration as i32),
+    }
+}
+
+/// Whether `file` of a `Video` segment is a round video message, sent with `sendVideoNote`.
+pub fn is_video_note(file: &File) -> bool {
+    file.mime
+        .as_ref()
+        .and_then(|mime| mime.get_param("round"))
+        .is_some_and(|round| round == "true")
+}
+
 pub fn parse_document(document: telegram_bot_api_rs::available_types::Document) -> MessageSegment {
     MessageSegment::file(File {
         id: Some(document.file_id.clone()),
@@ -167,15 +236,224 @@ pub fn parse_voice(voice: telegram_bot_api_rs::available_types::Voice) -> Messag
     )
 }
 
+/// Split `text` at its entity boundaries, keeping the entity segments in position.
+/// Offsets are in UTF-16 code units. Mentions and links replace the text they cover,
+/// overlapping ones after the first are left as text. Other entities are inserted before
+/// the text they cover, which stays in `Text` segments so that no text is lost;
+/// those starting inside a mention or link are dropped.
+pub fn parse_text(
+    text: String,
+    entities: Vec<MessageEntity>,
+    users: &UserCache,
+) -> Vec<MessageSegment> {
+    let units = text.encode_utf16().collect::<Vec<u16>>();
+    let mut replacements: Vec<(usize, usize, MessageSegment)> = Vec::new();
+    let mut markers = Vec::new();
+    for entity in entities {
+        let (offset, length) = entity_range(&entity);
+        let (start, end) = (offset as usize, (offset + length) as usize);
+        if end > units.len() {
+            continue;
+        }
+        match parse_message_entity(entity, String::from_utf16_lossy(&units[start..end]), users) {
+            segment @ MessageSegment::CustomValue { .. } => markers.push((start, segment)),
+            segment => replacements.push((start, end, segment)),
+        }
+    }
+    replacements.sort_by_key(|(start, end, _)| (*start, *end));
+    let mut covered = 0;
+    replacements.retain(|(start, end, _)| {
+        let keep = *start >= covered;
+        if keep {
+            covered = *end;
+        }
+        keep
+    });
+    markers.retain(|(position, _)| {
+        !replacements
+            .iter()
+            .any(|(start, end, _)| start < position && position < end)
+    });
+    markers.sort_by_key(|(position, _)| *position);
+
+    let mut segments = Vec::new();
+    let mut cursor = 0;
+    let flush = |segments: &mut Vec<MessageSegment>, cursor: &mut usize, to: usize| {
+        if to > *cursor {
+            segments.push(MessageSegment::text(String::from_utf16_lossy(
+                &units[*cursor..to],
+            )));
+            *cursor = to;
+        }
+    };
+    let mut markers = markers.into_iter().peekable();
+    for (start, end, segment) in replacements {
+        while let Some((position, marker)) = markers.next_if(|(position, _)| *position <= start) {
+            flush(&mut segments, &mut cursor, position);
+            segments.push(marker);
+        }
+        flush(&mut segments, &mut cursor, start);
+        segments.push(segment);
+        cursor = end;
+    }
+    for (position, marker) in markers {
+        flush(&mut segments, &mut cursor, position);
+        segments.push(marker);
+    }
+    flush(&mut segments, &mut cursor, units.len());
+    segments
+}
+
+/// The segment an entity covering `text` stands for.
+/// Mentions of known users become `At` and links become `Share`, replacing the text.
+/// Anything else becomes a `CustomValue` typed by the entity type (`"hashtag"`, `"bot_command"`,
+/// `"bold"`...) holding the covered `text` and the entity fields. It marks the start of the
+/// entity, the text itself stays in the following `Text` segments,
+/// and `send_message` turns it back into the entity over the text that follows.
 pub fn parse_message_entity(
     entity: telegram_bot_api_rs::available_types::MessageEntity,
-) -> Option<MessageSegment> {
-    match entity {
-        MessageEntity::TextMention { user, .. } => Some(MessageSegment::At {
-            user_id: user.id.to_string(),
-        }),
-        MessageEntity::TextLink { url, .. } => Some(MessageSegment::text(url)),
+    text: String,
+    users: &UserCache,
+) -> MessageSegment {
+    let mentioned = match &entity {
+        MessageEntity::Mention { .. } => users.get(&text),
         _ => None,
+    };
+    match (entity, mentioned) {
+        (MessageEntity::TextMention { user, .. }, _) => {
+            users.insert(&user);
+            MessageSegment::At {
+                user_id: user.id.to_string(),
+            }
+        }
+        (MessageEntity::Mention { .. }, Some(user_id)) => MessageSegment::At {
+            user_id: user_id.to_string(),
+        },
+        (MessageEntity::TextLink { url, .. }, _) => MessageSegment::Share {
+            title: text,
+            content: None,
+            url,
+            image: None,
+        },
+        (entity, _) => {
+            let mut data = serde_json::to_value(&entity).unwrap_or_default();
+            let r#type = data["type"].as_str().unwrap_or_default().to_string();
+            if let Some(fields) = data.as_object_mut() {
+                fields.remove("type");
+                fields.remove("offset");
+                fields.remove("length");
+                fields.insert("text".to_string(), text.into());
+            }
+            MessageSegment::CustomValue { r#type, data }
+        }
+    }
+}
+
+/// The covered text and entity of a `CustomValue` produced by `parse_message_entity`,
+/// placed at `offset` over `length` UTF-16 units. `None` for other custom values.
+pub fn entity_from_segment(
+    r#type: &str,
+    data: &serde_json::Value,
+    offset: i64,
+    length: i64,
+) -> Option<(String, MessageEntity)> {
+    let text = data.get("text")?.as_str()?.to_string();
+    let mut value = data.clone();
+    let fields = value.as_object_mut()?;
+    fields.remove("text");
+    fields.insert("type".to_string(), r#type.into());
+    fields.insert("offset".to_string(), offset.into());
+    fields.insert("length".to_string(), length.into());
+    let entity = serde_json::from_value(value).ok()?;
+    Some((text, entity))
+}
+
+/// An entity from a `CustomValue` marker, open until the text it covered has been sent.
+/// Its length is that of the text actually sent, which differs from the covered text
+/// when a mention inside it gets another label.
+struct OpenEntity {
+    /// Position of the entity in `OutgoingMessage::entities`
+    index: usize,
+    r#type: String,
+    data: serde_json::Value,
+    offset: i64,
+    /// Covered text not sent yet
+    remaining: Vec<u16>,
+    /// Whether a mention was sent since the last text, standing for an unknown part of `remaining`
+    after_mention: bool,
+}
+
+impl OpenEntity {
+    /// Consume text sent at `at`. Returns where the entity ends if it covers no further.
+    fn consume(&mut self, at: i64, content: &str) -> Option<i64> {
+        let content = content.encode_utf16().collect::<Vec<u16>>();
+        if self.after_mention {
+            self.after_mention = false;
+            // The mention stood for what comes before the first place this text fits
+            let skip = (1..=self.remaining.len())
+                .find(|&skip| {
+                    let rest = &self.remaining[skip..];
+                    rest.starts_with(&content) || content.starts_with(rest)
+                })
+                .unwrap_or(self.remaining.len());
+            self.remaining.drain(..skip);
+            if self.remaining.is_empty() {
+                return Some(at);
+            }
+        }
+        if content.len() >= self.remaining.len() {
+            return Some(at + self.remaining.len() as i64);
+        }
+        self.remaining.drain(..content.len());
+        None
+    }
+
+    fn close(self, end: i64, entities: &mut [MessageEntity]) {
+        if let Some((_, entity)) =
+            entity_from_segment(&self.r#type, &self.data, self.offset, end - self.offset)
+        {
+            entities[self.index] = entity;
+        }
+    }
+}
+
+/// Let the open entities consume `content`, sent at the end of `text`, closing those it ends.
+fn consume_text(
+    open: &mut Vec<OpenEntity>,
+    entities: &mut [MessageEntity],
+    text: &str,
+    content: &str,
+) {
+    let at = utf16_len(text);
+    for mut entity in std::mem::take(open) {
+        match entity.consume(at, content) {
+            Some(end) => entity.close(end, entities),
+            None => open.push(entity),
+        }
+    }
+}
+
+fn entity_range(entity: &MessageEntity) -> (i64, i64) {
+    match entity {
+        MessageEntity::Mention { offset, length }
+        | MessageEntity::Hashtag { offset, length }
+        | MessageEntity::Cashtag { offset, length }
+        | MessageEntity::BotCommand { offset, length }
+        | MessageEntity::Url { offset, length }
+        | MessageEntity::Email { offset, length }
+        | MessageEntity::PhoneNumber { offset, length }
+        | MessageEntity::Bold { offset, length }
+        | MessageEntity::Italic { offset, length }
+        | MessageEntity::Underline { offset, length }
+        | MessageEntity::Strikethrough { offset, length }
+        | MessageEntity::Spoiler { offset, length }
+        | MessageEntity::Blockquote { offset, length }
+        | MessageEntity::ExpandableBlockquote { offset, length }
+        | MessageEntity::Code { offset, length }
+        | MessageEntity::Pre { offset, length, .. }
+        | MessageEntity::TextLink { offset, length, .. }
+        | MessageEntity::TextMention { offset, length, .. }
+        | MessageEntity::CustomEmoji { offset, length, .. } => (*offset, *length),
     }
 }
 
@@ -192,6 +470,20 @@ pub fn parse_location(location: telegram_bot_api_rs::available_types::Location)
     MessageSegment::location(location.latitude, location.longitude, "".to_string(), None)
 }
 
+/// A shared contact, e.g. from a `ReplyKeyboard::request_contact` button,
+/// as a `CustomValue` of type `"contact"` holding the phone number, names, user id and vCard.
+pub fn parse_contact(contact: available_types::Contact) -> MessageSegment {
+    custom_value("contact", contact)
+}
+
+/// A message content without a generic segment, as a `CustomValue` holding the telegram object.
+fn custom_value(r#type: &str, data: impl Serialize) -> MessageSegment {
+    MessageSegment::CustomValue {
+        r#type: r#type.to_string(),
+        data: serde_json::to_value(data).unwrap_or_default(),
+    }
+}
+
 pub fn parse_reaction(reaction: ReactionType) -> String {
     match reaction {
         ReactionType::Emoji { emoji } => emoji,
@@ -200,190 +492,142 @@ pub fn parse_reaction(reaction: ReactionType) -> String {
     }
 }
 
+/// Outgoing segments split into what telegram sends separately.
+#[derive(Debug, Clone, Default)]
+pub struct OutgoingMessage {
+    pub text: String,
+    pub entities: Vec<MessageEntity>,
+    pub media: Vec<InputMedia>,
+    /// Files referenced by `attach://` names in `media`.
+    pub uploads: Vec<(String, File)>,
+    pub reply: Option<ReplyParameters>,
+    pub venues: Vec<Venue>,
+    pub stickers: Vec<String>,
+    /// Round video messages with their duration, see `is_video_note`.
+    pub video_notes: Vec<(File, Option<i64>)>,
+    /// Raw reply markup from a keyboard segment.
+    pub reply_markup: Option<serde_json::Value>,
+}
+
+/// Split outgoing segments into what telegram sends separately.
+/// Text and its entities are built together, `At` segments insert the label from `mention_labels`
+/// (falling back to the user id) at their position. Adjacent `Text` segments are joined by a
+/// newline, text around mentions, links and entities is joined directly.
+/// Entities from `CustomValue` markers cover the text sent until their covered text is used up,
+/// a mention inside them counts for whatever its label replaced.
 pub fn process_message_segments(
     message: Vec<MessageSegment>,
-) -> (
-    Vec<String>,
-    Vec<InputMedia>,
-    Option<ReplyParameters>,
-    Vec<MessageEntity>,
-    Vec<Venue>,
-    Vec<String>,
-) {
-    let mut text_segments = Vec::new();
+    mention_labels: &HashMap<String, String>,
+) -> Result<OutgoingMessage> {
+    let mut text = String::new();
     let mut media_segments = Vec::new();
     let mut reply = None;
     let mut entities = Vec::new();
     let mut venues = Vec::new();
     let mut stickers = Vec::new();
+    let mut video_notes = Vec::new();
+    let mut uploads = Vec::new();
+    let mut reply_markup = None;
+    // Whether the text so far ends with a `Text` segment
+    let mut previous_text = false;
+    let mut open_entities = Vec::new();
 
     for segment in message {
         match segment {
             MessageSegment::Text { content } => {
-                text_segments.push(content);
-            }
-            MessageSegment::Image { file } => {
-                if let Some(media_file) = file {
-                    if let Some(id) = media_file.id {
-                        media_segments.push(InputMedia::Photo {
-                            media: id.to_string(),
-                            caption: None,
-                            parse_mode: None,
-                            caption_entities: None,
-                            show_caption_above_media: None,
-                            has_spoiler: None,
-                        });
-                        tracing::debug!("Used id to send image");
-                    } else if let Some(uri) = &media_file.uri {
-                        media_segments.push(InputMedia::Photo {
-                            media: uri.to_string(),
-                            caption: None,
-                            parse_mode: None,
-                            caption_entities: None,
-                            show_caption_above_media: None,
-                            has_spoiler: None,
-                        });
-                        tracing::debug!("Used uri to send image");
-                    } else {
-                        tracing::warn!("No uri found for image");
-                    }
-                } else {
-                    tracing::warn!("No file found for image");
-                }
-            }
-            MessageSegment::Video { file, length } => {
-                if let Some(media_file) = file {
-                    if let Some(id) = media_file.id {
-                        media_segments.push(InputMedia::Video {
-                            media: id.to_string(),
-                            caption: None,
-                            parse_mode: None,
-                            caption_entities: None,
-                            duration: length.and_then(|l| Some(l as i64)),
-                            width: None,
-                            height: None,
-                            supports_streaming: None,
-                            has_spoiler: None,
-                            thumbnail: None,
-                            show_caption_above_media: None,
-                        });
-                        tracing::debug!("Used id to send video");
-                    } else if let Some(uri) = &media_file.uri {
-                        media_segments.push(InputMedia::Video {
-                            media: uri.to_string(),
-                            caption: None,
-                            parse_mode: None,
-                            caption_entities: None,
-                            duration: length.and_then(|l| Some(l as i64)),
-                            width: None,
-                            height: None,
-                            supports_streaming: None,
-                            has_spoiler: None,
-                            thumbnail: None,
-                            show_caption_above_media: None,
-                        });
-                        tracing::debug!("Used uri to send video");
-                    } else {
-                        tracing::warn!("No uri found for video");
-                    }
-                } else {
-                    tracing::warn!("No file found for video");
-                }
-            }
-            MessageSegment::Audio { file, length } => {
-                if let Some(media_file) = file {
-                    if let Some(id) = media_file.id {
-                        media_segments.push(InputMedia::Audio {
-                            media: id.to_string(),
-                            caption: None,
-                            parse_mode: None,
-                            caption_entities: None,
-                            duration: length.and_then(|l| Some(l as i64)),
-                            performer: None,
-                            title: None,
-                            thumbnail: None,
-                        });
-                        tracing::debug!("Used id to send audio");
-                    } else if let Some(uri) = &media_file.uri {
-                        media_segments.push(InputMedia::Audio {
-                            media: uri.to_string(),
-                            caption: None,
-                            parse_mode: None,
-                            caption_entities: None,
-                            duration: length.and_then(|l| Some(l as i64)),
-                            performer: None,
-                            title: None,
-                            thumbnail: None,
-                        });
-                        tracing::debug!("Used uri to send audio");
-                    } else {
-                        tracing::warn!("No uri found for audio");
-                    }
-                } else {
-                    tracing::warn!("No file found for audio");
+                if previous_text {
+                    text.push('\n');
                 }
+                consume_text(&mut open_entities, &mut entities, &text, &content);
+                text.push_str(&content);
+                previous_text = true;
             }
-            MessageSegment::File { file } => {
-                if let Some(media_file) = file {
-                    if let Some(id) = media_file.id {
-                        media_segments.push(InputMedia::Document {
-                            media: id.to_string(),
-                            caption: None,
-                            parse_mode: None,
-                            caption_entities: None,
-                            thumbnail: None,
-                            disable_content_type_detection: None,
-                        });
-                        tracing::debug!("Used id to send file");
-                    } else if let Some(uri) = &media_file.uri {
-                        media_segments.push(InputMedia::Document {
-                            media: uri.to_string(),
-                            caption: None,
-                            parse_mode: None,
-                            caption_entities: None,
-                            thumbnail: None,
-                            disable_content_type_detection: None,
-                        });
-                        tracing::debug!("Used uri to send file");
-                    } else {
-                        tracing::warn!("No uri found for file");
-                    }
-                } else {
-                    tracing::warn!("No file found for file");
-                }
+            MessageSegment::Image { file } => match media_reference(file, &mut uploads)? {
+                Some(media) => media_segments.push(InputMedia::Photo {
+                    media,
+                    caption: None,
+                    parse_mode: None,
+                    caption_entities: None,
+                    show_caption_above_media: None,
+                    has_spoiler: None,
+                }),
+                None => tracing::warn!("No file found for image"),
+            },
+            MessageSegment::Video {
+                file: Some(file),
+                length,
+            } if is_video_note(&file) && has_file(Some(&file)) => {
+                video_notes.push((file, length.map(|l| l as i64)))
             }
+            MessageSegment::Video { file, length } => match media_reference(file, &mut uploads)? {
+                Some(media) => media_segments.push(InputMedia::Video {
+                    media,
+                    caption: None,
+                    parse_mode: None,
+                    caption_entities: None,
+                    duration: length.and_then(|l| Some(l as i64)),
+                    width: None,
+                    height: None,
+                    supports_streaming: None,
+                    has_spoiler: None,
+                    thumbnail: None,
+                    show_caption_above_media: None,
+                }),
+                None => tracing::warn!("No file found for video"),
+            },
+            MessageSegment::Audio { file, length } => match media_reference(file, &mut uploads)? {
+                Some(media) => media_segments.push(InputMedia::Audio {
+                    media,
+                    caption: None,
+                    parse_mode: None,
+                    caption_entities: None,
+                    duration: length.and_then(|l| Some(l as i64)),
+                    performer: None,
+                    title: None,
+                    thumbnail: None,
+                }),
+                None => tracing::warn!("No file found for audio"),
+            },
+            MessageSegment::File { file } => match media_reference(file, &mut uploads)? {
+                Some(media) => media_segments.push(InputMedia::Document {
+                    media,
+                    caption: None,
+                    parse_mode: None,
+                    caption_entities: None,
+                    thumbnail: None,
+                    disable_content_type_detection: None,
+                }),
+                None => tracing::warn!("No file found for file"),
+            },
             MessageSegment::Share {
                 title,
                 content,
                 url,
                 image,
             } => {
-                let mut caption = title;
-                if let Some(desc) = content {
-                    caption.push_str(&format!("\n{}", desc));
-                }
-                if let Some(media_file) = image {
-                    if let Some(id) = media_file.id {
-                        media_segments.push(InputMedia::Photo {
-                            media: id.to_string(),
-                            caption: Some(caption),
-                            parse_mode: None,
-                            caption_entities: None,
-                            show_caption_above_media: None,
-                            has_spoiler: None,
-                        });
-                    } else if let Some(uri) = &media_file.uri {
-                        media_segments.push(InputMedia::Photo {
-                            media: uri.to_string(),
-                            caption: Some(caption),
-                            parse_mode: None,
-                            caption_entities: None,
-                            show_caption_above_media: None,
-                            has_spoiler: None,
+                let desc = content
+                    .map(|desc| format!("\n{}", desc))
+                    .unwrap_or_default();
+                match media_reference(image, &mut uploads)? {
+                    Some(media) => media_segments.push(InputMedia::Photo {
+                        media,
+                        caption: Some(format!("{}{}", title, desc)),
+                        parse_mode: None,
+                        caption_entities: None,
+                        show_caption_above_media: None,
+                        has_spoiler: None,
+                    }),
+                    None => {
+                        entities.push(MessageEntity::TextLink {
+                            offset: utf16_len(&text),
+                            length: utf16_len(&title),
+                            url,
                         });
+                        let content = format!("{}{}", title, desc);
+                        consume_text(&mut open_entities, &mut entities, &text, &content);
+                        text.push_str(&content);
+                        previous_text = false;
                     }
-                } else {
-                    text_segments.push(format!("{}\n{}", caption, url));
                 }
             }
             MessageSegment::Reply { message_id } => match split_id(message_id) {
@@ -407,14 +651,20 @@ pub fn process_message_segments(
             },
             MessageSegment::At { user_id } => {
                 if let Ok(id) = user_id.parse() {
+                    let label = mention_labels.get(&user_id).unwrap_or(&user_id);
                     entities.push(MessageEntity::TextMention {
-                        offset: 0,
-                        length: 0,
+                        offset: utf16_len(&text),
+                        length: utf16_len(label),
                         user: User {
                             id: id,
                             ..Default::default()
                         },
                     });
+                    text.push_str(label);
+                    for entity in &mut open_entities {
+                        entity.after_mention = true;
+                    }
+                    previous_text = false;
                 } else {
                     tracing::warn!("Invalid user id for mention");
                 }
@@ -451,18 +701,637 @@ pub fn process_message_segments(
             MessageSegment::CustomString { .. } => {
                 tracing::error!("Custom string is not supported in telegram");
             }
-            MessageSegment::CustomValue { .. } => {
-                tracing::error!("Custom value is not supported in telegram");
+            MessageSegment::CustomValue { r#type, data } if is_reply_markup(&r#type) => {
+                reply_markup = Some(data);
+            }
+            // The entity covers the text that follows it
+            MessageSegment::CustomValue { r#type, data } => {
+                let offset = utf16_len(&text);
+                match entity_from_segment(&r#type, &data, offset, 0) {
+                    Some((covered, entity)) => {
+                        open_entities.push(OpenEntity {
+                            index: entities.len(),
+                            r#type,
+                            data,
+                            offset,
+                            remaining: covered.encode_utf16().collect(),
+                            after_mention: false,
+                        });
+                        entities.push(entity);
+                        previous_text = false;
+                    }
+                    None => tracing::error!("Custom value is not supported in telegram"),
+                }
             }
         }
     }
+    for entity in open_entities {
+        entity.close(utf16_len(&text), &mut entities);
+    }
 
-    (
-        text_segments,
-        media_segments,
-        reply,
+    Ok(OutgoingMessage {
+        text,
         entities,
+        media: media_segments,
+        uploads,
+        reply,
         venues,
         stickers,
-    )
+        video_notes,
+        reply_markup,
+    })
+}
+
+/// Set the caption of a media.
+pub fn set_caption(media: &mut InputMedia, text: String, entities: Vec<MessageEntity>) {
+    match media {
+        InputMedia::Photo {
+            caption,
+            caption_entities,
+            ..
+        }
+        | InputMedia::Video {
+            caption,
+            caption_entities,
+            ..
+        }
+        | InputMedia::Animation {
+            caption,
+            caption_entities,
+            ..
+        }
+        | InputMedia::Document {
+            caption,
+            caption_entities,
+            ..
+        }
+        | InputMedia::Audio {
+            caption,
+            caption_entities,
+            ..
+        } => {
+            *caption = Some(text);
+            *caption_entities = Some(entities);
+        }
+    }
+}
+
+/// Reference a `File` in an outgoing request, `None` if there is nothing to send:
+/// - its telegram file id or http(s) `uri` is sent as is
+/// - a local path `uri` (see `utils::local_path`) or its `base64` is uploaded as multipart,
+///   through an `attach://` name pushed to `uploads`
+///
+/// Any other uri scheme is an error.
+fn media_reference(
+    file: Option<File>,
+    uploads: &mut Vec<(String, File)>,
+) -> Result<Option<String>> {
+    let Some(file) = file else {
+        return Ok(None);
+    };
+    if let Some(id) = file.id {
+        return Ok(Some(id));
+    }
+    match &file.uri {
+        Some(uri) if utils::local_path(uri)?.is_none() => return Ok(Some(uri.to_string())),
+        Some(_) => {}
+        None if file.base64.is_some() => {}
+        None => return Ok(None),
+    }
+    let name = format!("file{}", uploads.len());
+    uploads.push((name.clone(), file));
+    Ok(Some(format!("attach://{}", name)))
+}
+
+/// What `send_message` and `edit_messagee` do with segments telegram can't represent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SegmentPolicy {
+    /// Fail with `TelegramError::UnsupportedSegments` listing them.
+    Strict,
+    /// Log and drop them.
+    #[default]
+    Lossy,
+    /// Render them as text, `AtAll` becomes mentions of the group admins
+    /// (`@all` if they can't be fetched).
+    Fallback,
+}
+
+/// Name of the segment if telegram can't represent it, or it would be dropped when sent:
+/// an `At` with a non numeric user id, media without a file id, uri or base64,
+/// a `Reply` with an invalid message id.
+pub fn unsupported_segment(segment: &MessageSegment) -> Option<String> {
+    match segment {
+        MessageSegment::At { user_id } if user_id.parse::<i64>().is_err() => {
+            Some(format!("At({})", user_id))
+        }
+        MessageSegment::Image { file } if !has_file(file.as_ref()) => Some("Image".to_string()),
+        MessageSegment::Video { file, .. } if !has_file(file.as_ref()) => Some("Video".to_string()),
+        MessageSegment::Audio { file, .. } if !has_file(file.as_ref()) => Some("Audio".to_string()),
+        MessageSegment::File { file } if !has_file(file.as_ref()) => Some("File".to_string()),
+        MessageSegment::Reply { message_id }
+            if split_id(message_id.clone())
+                .ok()
+                .and_then(|(_, id)| id.parse::<i64>().ok())
+                .is_none() =>
+        {
+            Some(format!("Reply({})", message_id))
+        }
+        MessageSegment::AtAll => Some("AtAll".to_string()),
+        MessageSegment::Reference { .. } => Some("Reference".to_string()),
+        MessageSegment::ForwardNode { .. } => Some("ForwardNode".to_string()),
+        MessageSegment::ForwardCustomNode { .. } => Some("ForwardCustomNode".to_string()),
+        MessageSegment::CustomString { r#type, .. } => Some(format!("CustomString({})", r#type)),
+        MessageSegment::CustomValue { r#type, data }
+            if !is_reply_markup(r#type) && entity_from_segment(r#type, data, 0, 0).is_none() =>
+        {
+            Some(format!("CustomValue({})", r#type))
+        }
+        _ => None,
+    }
+}
+
+/// Whether a media segment has something `media_reference` can send.
+fn has_file(file: Option<&File>) -> bool {
+    file.is_some_and(|file| file.id.is_some() || file.uri.is_some() || file.base64.is_some())
+}
+
+/// Text rendering of an unsupported segment. `AtAll` is only rendered as is
+/// when the caller can't mention the group admins instead.
+pub fn fallback_text(segment: MessageSegment) -> String {
+    match segment {
+        MessageSegment::AtAll => "@all".to_string(),
+        MessageSegment::At { user_id } => format!("@{}", user_id),
+        MessageSegment::Image { .. } => "[image]".to_string(),
+        MessageSegment::Video { .. } => "[video]".to_string(),
+        MessageSegment::Audio { .. } => "[audio]".to_string(),
+        MessageSegment::File { .. } => "[file]".to_string(),
+        MessageSegment::Reference { message_id } => format!("> [message {}]\n", message_id),
+        MessageSegment::ForwardNode { message_id } => {
+            format!("> [forwarded message {}]\n", message_id)
+        }
+        MessageSegment::ForwardCustomNode { user, message } => {
+            let sender = user
+                .map(|user| {
+                    user.profile
+                        .and_then(|profile| profile.nickname)
+                        .unwrap_or(user.id)
+                })
+                .unwrap_or_default();
+            let mut transcript = String::new();
+            for line in message.get_raw_text().lines() {
+                transcript.push_str(&format!("> {}: {}\n", sender, line));
+            }
+            transcript
+        }
+        MessageSegment::CustomString { data, .. } => data,
+        MessageSegment::CustomValue { r#type, data } => format!("[{}] {}", r#type, data),
+        _ => String::new(),
+    }
+}
+
+impl TelegramBot {
+    /// Apply `TelegramBotOptions::segment_policy` to an outgoing message for `chat_id`.
+    pub(crate) async fn apply_segment_policy(
+        &self,
+        chat_id: &str,
+        message: Vec<MessageSegment>,
+    ) -> Result<Vec<MessageSegment>> {
+        match self.options.segment_policy {
+            SegmentPolicy::Lossy => Ok(message),
+            SegmentPolicy::Strict => {
+                let segments = message
+                    .iter()
+                    .filter_map(unsupported_segment)
+                    .collect::<Vec<_>>();
+                if segments.is_empty() {
+                    Ok(message)
+                } else {
+                    Err(TelegramError::UnsupportedSegments { segments }.into())
+                }
+            }
+            SegmentPolicy::Fallback => {
+                let mut segments = Vec::new();
+                for segment in message {
+                    match segment {
+                        MessageSegment::AtAll => {
+                            let admins = match self
+                                .bot
+                                .get_chat_administrators(&ChatIdPayload {
+                                    chat_id: chat_id.to_string(),
+                                })
+                                .await
+                            {
+                                Ok(admins) => admins,
+                                Err(e) => {
+                                    tracing::warn!("Error while getting administrators: {:?}", e);
+                                    segments.push(MessageSegment::text(fallback_text(
+                                        MessageSegment::AtAll,
+                                    )));
+                                    continue;
+                                }
+                            };
+                            for admin in admins {
+                                if let ChatMember::Owner { user, .. }
+                                | ChatMember::Administrator { user, .. } = admin
+                                {
+                                    if !user.is_bot {
+                                        segments.push(MessageSegment::At {
+                                            user_id: user.id.to_string(),
+                                        });
+                                        segments.push(MessageSegment::text(" "));
+                                    }
+                                }
+                            }
+                        }
+                        segment if unsupported_segment(&segment).is_some() => {
+                            let text = fallback_text(segment);
+                            if !text.is_empty() {
+                                segments.push(MessageSegment::text(text))
+                            }
+                        }
+                        segment => segments.push(segment),
+                    }
+                }
+                Ok(segments)
+            }
+        }
+    }
+}
+
+/// Visible text inserted for an outgoing `At` segment.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum MentionLabel {
+    /// The user's name, looked up in the chat. Falls back to the user id.
+    #[default]
+    Nickname,
+    /// The same text for every mention.
+    Placeholder(String),
+}
+
+impl TelegramBot {
+    /// Labels of the users mentioned by `At` segments in `message`, keyed by user id.
+    pub(crate) async fn mention_labels(
+        &self,
+        chat_id: &str,
+        message: &[MessageSegment],
+    ) -> HashMap<String, String> {
+        let mut labels = HashMap::new();
+        for segment in message {
+            let MessageSegment::At { user_id } = segment else {
+                continue;
+            };
+            if labels.contains_key(user_id) {
+                continue;
+            }
+            let label = match &self.options.mention_label {
+                MentionLabel::Placeholder(placeholder) => placeholder.clone(),
+                MentionLabel::Nickname => match self.mention_nickname(chat_id, user_id).await {
+                    Ok(nickname) => nickname,
+                    Err(e) => {
+                        tracing::debug!("Error while getting nickname of {}: {:?}", user_id, e);
+                        continue;
+                    }
+                },
+            };
+            labels.insert(user_id.clone(), label);
+        }
+        labels
+    }
+
+    async fn mention_nickname(&self, chat_id: &str, user_id: &str) -> Result<String> {
+        let member = self
+            .bot
+            .get_chat_member(&GetChatMemberPayload {
+                chat_id: chat_id.to_string(),
+                user_id: user_id.parse()?,
+            })
+            .await?;
+        let user = chat_member_user(member);
+        Ok(match user.last_name {
+            Some(last_name) => format!("{} {}", user.first_name, last_name),
+            None => user.first_name,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str, entities: Vec<MessageEntity>) -> Vec<MessageSegment> {
+        parse_text(text.to_string(), entities, &UserCache::default())
+    }
+
+    #[test]
+    fn entities_keep_their_text() {
+        let segments = parse(
+            "/start foo https://x.com",
+            vec![
+                MessageEntity::BotCommand {
+                    offset: 0,
+                    length: 6,
+                },
+                MessageEntity::Url {
+                    offset: 11,
+                    length: 13,
+                },
+            ],
+        );
+        let message = Message {
+            id: String::new(),
+            segments: segments.clone(),
+        };
+        assert_eq!(message.get_raw_text(), "/start foo https://x.com");
+        assert!(matches!(
+            &segments[0],
+            MessageSegment::CustomValue { r#type, data }
+                if r#type == "bot_command" && data["text"] == "/start"
+        ));
+    }
+
+    #[test]
+    fn mentions_win_over_formatting() {
+        let segments = parse(
+            "hello bob there",
+            vec![
+                MessageEntity::Bold {
+                    offset: 0,
+                    length: 15,
+                },
+                MessageEntity::TextMention {
+                    offset: 6,
+                    length: 3,
+                    user: User {
+                        id: 42,
+                        ..Default::default()
+                    },
+                },
+            ],
+        );
+        assert_eq!(
+            segments,
+            vec![
+                MessageSegment::CustomValue {
+                    r#type: "bold".to_string(),
+                    data: json!({ "text": "hello bob there" }),
+                },
+                MessageSegment::text("hello "),
+                MessageSegment::At {
+                    user_id: "42".to_string(),
+                },
+                MessageSegment::text(" there"),
+            ]
+        );
+    }
+
+    fn outgoing(segments: Vec<MessageSegment>) -> OutgoingMessage {
+        let labels = HashMap::from([("42".to_string(), "@bob".to_string())]);
+        process_message_segments(segments, &labels).unwrap()
+    }
+
+    #[test]
+    fn adjacent_text_is_joined_by_newline() {
+        let message = outgoing(vec![
+            MessageSegment::text("hello"),
+            MessageSegment::text("world"),
+            MessageSegment::At {
+                user_id: "42".to_string(),
+            },
+            MessageSegment::text("!"),
+        ]);
+        assert_eq!(message.text, "hello\nworld@bob!");
+    }
+
+    #[test]
+    fn mention_offsets_count_utf16_units() {
+        assert_eq!(utf16_len("a😀"), 3);
+        let message = outgoing(vec![
+            MessageSegment::text("😀 hi "),
+            MessageSegment::At {
+                user_id: "42".to_string(),
+            },
+            MessageSegment::text(" é"),
+            MessageSegment::At {
+                user_id: "7".to_string(),
+            },
+        ]);
+        assert_eq!(message.text, "😀 hi @bob é7");
+        let ranges = message
+            .entities
+            .iter()
+            .map(entity_range)
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, vec![(6, 4), (12, 1)]);
+    }
+
+    #[test]
+    fn entity_segments_cover_following_text() {
+        let segments = parse(
+            "😀 #tag",
+            vec![MessageEntity::Hashtag {
+                offset: 3,
+                length: 4,
+            }],
+        );
+        let message = outgoing(segments);
+        assert_eq!(message.text, "😀 #tag");
+        let ranges = message
+            .entities
+            .iter()
+            .map(entity_range)
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, vec![(3, 4)]);
+    }
+
+    #[test]
+    fn media_is_referenced_or_uploaded() {
+        let image = |file: File| MessageSegment::Image { file: Some(file) };
+        let message = outgoing(vec![
+            image(File {
+                id: Some("AgACfileid".to_string()),
+                ..Default::default()
+            }),
+            image(File {
+                uri: Some("https://x.com/a.png".parse().unwrap()),
+                ..Default::default()
+            }),
+            image(File {
+                uri: Some("/tmp/a.png".parse().unwrap()),
+                ..Default::default()
+            }),
+            image(File {
+                base64: Some("aGk=".to_string()),
+                ..Default::default()
+            }),
+            MessageSegment::Image { file: None },
+        ]);
+        let media = message
+            .media
+            .iter()
+            .map(|media| match media {
+                InputMedia::Photo { media, .. } => media.as_str(),
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            media,
+            vec![
+                "AgACfileid",
+                "https://x.com/a.png",
+                "attach://file0",
+                "attach://file1"
+            ]
+        );
+        let uploads = message
+            .uploads
+            .iter()
+            .map(|(name, file)| (name.as_str(), file.uri.is_some(), file.base64.is_some()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            uploads,
+            vec![("file0", true, false), ("file1", false, true)]
+        );
+    }
+
+    #[test]
+    fn unsupported_uri_scheme_is_an_error() {
+        let segments = vec![MessageSegment::Image {
+            file: Some(File {
+                uri: Some("ftp://x.com/a.png".parse().unwrap()),
+                ..Default::default()
+            }),
+        }];
+        assert!(process_message_segments(segments, &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn dropped_segments_are_unsupported() {
+        let file = |file: Option<File>| MessageSegment::Image { file };
+        let at = |user_id: &str| MessageSegment::At {
+            user_id: user_id.to_string(),
+        };
+        let reply = |message_id: &str| MessageSegment::Reply {
+            message_id: message_id.to_string(),
+        };
+        let names = [
+            at("bob"),
+            file(None),
+            file(Some(File::default())),
+            MessageSegment::File { file: None },
+            reply("42"),
+            reply("-1001_x"),
+            MessageSegment::AtAll,
+        ]
+        .iter()
+        .map(|segment| unsupported_segment(segment).unwrap())
+        .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "At(bob)",
+                "Image",
+                "Image",
+                "File",
+                "Reply(42)",
+                "Reply(-1001_x)",
+                "AtAll"
+            ]
+        );
+        for segment in [
+            at("42"),
+            file(Some(File {
+                id: Some("AgACfileid".to_string()),
+                ..Default::default()
+            })),
+            reply("-1001_42"),
+            MessageSegment::text("hi"),
+        ] {
+            assert_eq!(unsupported_segment(&segment), None);
+        }
+    }
+
+    #[test]
+    fn fallback_text_renders_unsupported_segments() {
+        assert_eq!(fallback_text(MessageSegment::AtAll), "@all");
+        assert_eq!(
+            fallback_text(MessageSegment::At {
+                user_id: "bob".to_string()
+            }),
+            "@bob"
+        );
+        assert_eq!(
+            fallback_text(MessageSegment::Video {
+                file: None,
+                length: None
+            }),
+            "[video]"
+        );
+        assert_eq!(
+            fallback_text(MessageSegment::Reference {
+                message_id: "-1001_42".to_string()
+            }),
+            "> [message -1001_42]\n"
+        );
+        assert_eq!(
+            fallback_text(MessageSegment::CustomString {
+                r#type: "dice".to_string(),
+                data: "6".to_string()
+            }),
+            "6"
+        );
+        assert_eq!(
+            fallback_text(MessageSegment::Reply {
+                message_id: "42".to_string()
+            }),
+            ""
+        );
+    }
+
+    #[test]
+    fn entities_stretch_over_longer_mention_labels() {
+        let segments = parse(
+            "hello bob there",
+            vec![
+                MessageEntity::Bold {
+                    offset: 0,
+                    length: 15,
+                },
+                MessageEntity::TextMention {
+                    offset: 6,
+                    length: 3,
+                    user: User {
+                        id: 42,
+                        ..Default::default()
+                    },
+                },
+            ],
+        );
+        let labels = HashMap::from([("42".to_string(), "Robert Smith".to_string())]);
+        let message = process_message_segments(segments, &labels).unwrap();
+        assert_eq!(message.text, "hello Robert Smith there");
+        let ranges = message
+            .entities
+            .iter()
+            .map(entity_range)
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, vec![(0, 24), (6, 12)]);
+    }
+
+    #[test]
+    fn video_notes_are_sent_on_their_own() {
+        let video = |mime: Option<&str>| MessageSegment::Video {
+            file: Some(File {
+                id: Some("DQACfileid".to_string()),
+                mime: mime.map(|mime| mime.parse().unwrap()),
+                ..Default::default()
+            }),
+            length: Some(5),
+        };
+        let message = outgoing(vec![video(Some(VIDEO_NOTE_MIME)), video(None)]);
+        assert_eq!(message.media.len(), 1);
+        assert_eq!(message.video_notes.len(), 1);
+        let (file, duration) = &message.video_notes[0];
+        assert!(is_video_note(file));
+        assert_eq!(*duration, Some(5));
+    }
 }
@@ -0,0 +1,377 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use anyhow::Result;
+use telegram_bot_api_rs::available_types::{
+    BusinessConnection, ChatPermissions, Message, Poll, User,
+};
+use tokio::sync::RwLock;
+
+use crate::utils::split_id;
+
+/// Snapshots of group permissions taken by `mute_group`, so that unmuting restores them.
+/// Kept in memory, and written to `path` as json on every change if a path is given.
+#[derive(Debug, Clone, Default)]
+pub struct PermissionStore {
+    snapshots: Arc<RwLock<HashMap<String, ChatPermissions>>>,
+    path: Option<PathBuf>,
+}
+
+impl PermissionStore {
+    pub async fn load(path: Option<PathBuf>) -> Result<Self> {
+        let snapshots = match &path {
+            Some(path) if path.is_file() => serde_json::from_slice(&tokio::fs::read(path).await?)?,
+            _ => HashMap::new(),
+        };
+        Ok(Self {
+            snapshots: Arc::new(RwLock::new(snapshots)),
+            path,
+        })
+    }
+
+    pub async fn get(&self, group_id: &str) -> Option<ChatPermissions> {
+        self.snapshots.read().await.get(group_id).cloned()
+    }
+
+    pub async fn insert(&self, group_id: String, permissions: ChatPermissions) -> Result<()> {
+        let mut snapshots = self.snapshots.write().await;
+        snapshots.insert(group_id, permissions);
+        self.persist(&snapshots).await
+    }
+
+    pub async fn remove(&self, group_id: &str) -> Result<Option<ChatPermissions>> {
+        let mut snapshots = self.snapshots.write().await;
+        let permissions = snapshots.remove(group_id);
+        self.persist(&snapshots).await?;
+        Ok(permissions)
+    }
+
+    async fn persist(&self, snapshots: &HashMap<String, ChatPermissions>) -> Result<()> {
+        if let Some(path) = &self.path {
+            tokio::fs::write(path, serde_json::to_vec(snapshots)?).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Ids of the users seen in updates keyed by lowercase username, so that `@username`
+/// mentions can be resolved. Lives in memory only.
+#[derive(Debug, Clone, Default)]
+pub struct UserCache {
+    users: Arc<std::sync::RwLock<HashMap<String, i64>>>,
+}
+
+impl UserCache {
+    pub fn insert(&self, user: &User) {
+        if let Some(username) = &user.username {
+            self.users
+                .write()
+                .unwrap()
+                .insert(username.to_lowercase(), user.id);
+        }
+    }
+
+    /// Resolve a username, with or without the leading `@`.
+    pub fn get(&self, username: &str) -> Option<i64> {
+        let username = username.trim_start_matches('@').to_lowercase();
+        self.users.read().unwrap().get(&username).copied()
+    }
+}
+
+/// A poll sent by `TelegramBot::send_poll`.
+#[derive(Debug, Clone)]
+pub struct SentPoll {
+    /// The message holding the poll, as `{chat_id}_{message_id}`.
+    pub message_id: String,
+    /// Latest state of the poll, refreshed by `Poll` updates.
+    pub poll: Poll,
+}
+
+/// Polls sent by the bot keyed by poll id, so that `PollAnswer` updates, which only carry
+/// the poll id, can be related to the poll. Closed polls are dropped. Lives in memory only.
+#[derive(Debug, Clone, Default)]
+pub struct PollRegistry {
+    polls: Arc<std::sync::RwLock<HashMap<String, SentPoll>>>,
+}
+
+impl PollRegistry {
+    pub fn insert(&self, message_id: String, poll: Poll) {
+        self.polls
+            .write()
+            .unwrap()
+            .insert(poll.id.clone(), SentPoll { message_id, poll });
+    }
+
+    pub fn get(&self, poll_id: &str) -> Option<SentPoll> {
+        self.polls.read().unwrap().get(poll_id).cloned()
+    }
+
+    /// Refresh a known poll from a `Poll` update, dropping it once closed.
+    pub fn update(&self, poll: &Poll) {
+        let mut polls = self.polls.write().unwrap();
+        if poll.is_closed {
+            polls.remove(&poll.id);
+        } else if let Some(sent) = polls.get_mut(&poll.id) {
+            sent.poll = poll.clone();
+        }
+    }
+
+    pub fn remove(&self, poll_id: &str) -> Option<SentPoll> {
+        self.polls.write().unwrap().remove(poll_id)
+    }
+}
+
+/// Result ids answered to the latest inline query of each user, so that a `ChosenInlineResult`,
+/// which only carries the result id and query text, can be related to the query id.
+/// Pages of the same query are kept together. Lives in memory only.
+#[derive(Debug, Clone, Default)]
+pub struct InlineQueryRegistry {
+    queries: Arc<std::sync::RwLock<HashMap<i64, AnsweredQuery>>>,
+}
+
+#[derive(Debug, Default)]
+struct AnsweredQuery {
+    query: String,
+    /// Query id keyed by result id.
+    results: HashMap<String, String>,
+}
+
+impl InlineQueryRegistry {
+    pub fn insert(&self, user_id: i64, query: &str, query_id: &str, result_ids: Vec<String>) {
+        let mut queries = self.queries.write().unwrap();
+        let answered = queries.entry(user_id).or_default();
+        if answered.query != query {
+            answered.query = query.to_string();
+            answered.results.clear();
+        }
+        for result_id in result_ids {
+            answered.results.insert(result_id, query_id.to_string());
+        }
+    }
+
+    /// The query id `result_id` was answered to.
+    pub fn get(&self, user_id: i64, query: &str, result_id: &str) -> Option<String> {
+        let queries = self.queries.read().unwrap();
+        let answered = queries
+            .get(&user_id)
+            .filter(|answered| answered.query == query)?;
+        answered.results.get(result_id).cloned()
+    }
+}
+
+/// How many business messages `BusinessRegistry` remembers the connection of.
+const BUSINESS_MESSAGE_CAPACITY: usize = 4096;
+
+/// Business connections of the bot, and the connection recent business messages came or were
+/// sent through, so that `send_message` replying to one goes out on behalf of the business
+/// account. Lives in memory only.
+#[derive(Debug, Clone)]
+pub struct BusinessRegistry {
+    connections: Arc<std::sync::RwLock<HashMap<String, BusinessConnection>>>,
+    messages: Arc<std::sync::Mutex<LruCache<String>>>,
+}
+
+impl Default for BusinessRegistry {
+    fn default() -> Self {
+        Self {
+            connections: Default::default(),
+            messages: Arc::new(std::sync::Mutex::new(LruCache::new(
+                BUSINESS_MESSAGE_CAPACITY,
+            ))),
+        }
+    }
+}
+
+impl BusinessRegistry {
+    pub fn update_connection(&self, connection: &BusinessConnection) {
+        self.connections
+            .write()
+            .unwrap()
+            .insert(connection.id.clone(), connection.clone());
+    }
+
+    pub fn connection(&self, connection_id: &str) -> Option<BusinessConnection> {
+        self.connections.read().unwrap().get(connection_id).cloned()
+    }
+
+    /// Record the connection a message came or was sent through, by its oxidebot id.
+    pub fn record_message(&self, message_id: &str, connection_id: String) {
+        if let Ok((chat_id, message_id)) = split_id(message_id.to_string()) {
+            self.messages
+                .lock()
+                .unwrap()
+                .put(format!("{}_{}", chat_id, message_id), connection_id);
+        }
+    }
+
+    /// The connection a recent business message came or was sent through.
+    pub fn message_connection(&self, message_id: &str) -> Option<String> {
+        let (chat_id, message_id) = split_id(message_id.to_string()).ok()?;
+        self.messages
+            .lock()
+            .unwrap()
+            .get(&format!("{}_{}", chat_id, message_id))
+    }
+}
+
+/// Persistent storage behind a `MessageStore`, e.g. a SQLite table, keeping history
+/// beyond the in-memory capacity and across restarts. Messages are keyed by
+/// `{chat_id}_{message_id}`. Called while handling updates, so it should be quick.
+pub trait MessageBackend: std::fmt::Debug + Send + Sync {
+    /// Save a message, replacing the one with the same key.
+    fn save(&self, key: &str, message: &Message) -> Result<()>;
+
+    fn load(&self, key: &str) -> Result<Option<Message>>;
+}
+
+/// History of the messages received and sent by the bot, since Telegram can't fetch a
+/// message by id. The latest `capacity` messages are kept in memory, and all of them in
+/// `backend` if one is given. Records nothing if neither is set.
+#[derive(Debug, Clone, Default)]
+pub struct MessageStore {
+    cache: Arc<std::sync::Mutex<LruCache<Message>>>,
+    backend: Option<Arc<dyn MessageBackend>>,
+}
+
+/// Least recently used values, ordered by the tick they were last used at.
+/// Keeps nothing if `capacity` is `0`.
+#[derive(Debug, Default)]
+struct LruCache<V> {
+    capacity: usize,
+    tick: u64,
+    values: HashMap<String, (u64, V)>,
+    order: BTreeMap<u64, String>,
+}
+
+impl<V: Clone> LruCache<V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            tick: 0,
+            values: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    fn put(&mut self, key: String, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.tick += 1;
+        if let Some((tick, _)) = self.values.insert(key.clone(), (self.tick, value)) {
+            self.order.remove(&tick);
+        }
+        self.order.insert(self.tick, key);
+        while self.values.len() > self.capacity {
+            let Some((_, key)) = self.order.pop_first() else {
+                break;
+            };
+            self.values.remove(&key);
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<V> {
+        self.tick += 1;
+        let (tick, value) = self.values.get_mut(key)?;
+        self.order.remove(tick);
+        *tick = self.tick;
+        self.order.insert(self.tick, key.to_string());
+        Some(value.clone())
+    }
+}
+
+impl MessageStore {
+    pub fn new(capacity: usize, backend: Option<Arc<dyn MessageBackend>>) -> Self {
+        Self {
+            cache: Arc::new(std::sync::Mutex::new(LruCache::new(capacity))),
+            backend,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.backend.is_some() || self.cache.lock().unwrap().capacity > 0
+    }
+
+    /// Record a message, replacing an older version of it.
+    pub fn insert(&self, message: &Message) {
+        let key = format!("{}_{}", message.chat.id, message.message_id);
+        if let Some(backend) = &self.backend {
+            if let Err(e) = backend.save(&key, message) {
+                tracing::error!("Error while saving message {}: {:?}", key, e);
+            }
+        }
+        self.cache.lock().unwrap().put(key, message.clone());
+    }
+
+    /// The latest recorded version of a message, by its oxidebot id.
+    pub fn get(&self, message_id: &str) -> Option<Message> {
+        let (chat_id, message_id) = split_id(message_id.to_string()).ok()?;
+        let key = format!("{}_{}", chat_id, message_id);
+        if let Some(message) = self.cache.lock().unwrap().get(&key) {
+            return Some(message);
+        }
+        let message = match self.backend.as_ref()?.load(&key) {
+            Ok(message) => message?,
+            Err(e) => {
+                tracing::error!("Error while loading message {}: {:?}", key, e);
+                return None;
+            }
+        };
+        self.cache.lock().unwrap().put(key, message.clone());
+        Some(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(chat_id: i64, message_id: i64, text: &str) -> Message {
+        Message {
+            message_id,
+            chat: telegram_bot_api_rs::available_types::Chat {
+                id: chat_id,
+                ..Default::default()
+            },
+            text: Some(text.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn lru_cache_evicts_least_recently_used() {
+        let mut cache = LruCache::new(2);
+        cache.put("a".to_string(), 1);
+        cache.put("b".to_string(), 2);
+        assert_eq!(cache.get("a"), Some(1));
+        cache.put("c".to_string(), 3);
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("a"), Some(1));
+        assert_eq!(cache.get("c"), Some(3));
+        cache.put("a".to_string(), 4);
+        assert_eq!(cache.get("a"), Some(4));
+        assert_eq!(cache.values.len(), cache.order.len());
+    }
+
+    #[test]
+    fn lru_cache_without_capacity_keeps_nothing() {
+        let mut cache = LruCache::new(0);
+        cache.put("a".to_string(), 1);
+        assert_eq!(cache.get("a"), None);
+    }
+
+    #[test]
+    fn message_store_looks_up_by_message_id() {
+        let store = MessageStore::new(10, None);
+        store.insert(&message(1, 5, "old"));
+        store.insert(&message(1, 5, "new"));
+        assert_eq!(store.get("1_5").unwrap().text.as_deref(), Some("new"));
+        // Thread ids of topic messages are ignored
+        assert!(store.get("1_5_3").is_some());
+        assert!(store.get("1_6").is_none());
+        assert!(!MessageStore::default().is_enabled());
+    }
+}
@@ -1,6 +1,8 @@
 use anyhow::Result;
+use hyper::Uri;
 use oxidebot::source::{
     group::{Group, GroupProfile},
+    message::File,
     user::{User, UserProfile},
 };
 
@@ -32,6 +34,18 @@ pub fn parse_group(group: telegram_bot_api_rs::available_types::Chat) -> Group {
     }
 }
 
+/// The other user of a private chat, whose id is the chat id.
+pub fn parse_private_chat(chat: telegram_bot_api_rs::available_types::Chat) -> User {
+    User {
+        id: chat.id.to_string(),
+        profile: Some(UserProfile {
+            nickname: chat.username,
+            ..Default::default()
+        }),
+        group_info: None,
+    }
+}
+
 pub fn split_id(id: String) -> Result<(String, String)> {
     let mut iter = id.split('_');
 
@@ -46,3 +60,123 @@ pub fn split_id(id: String) -> Result<(String, String)> {
 
     Ok((chat_id, message_id))
 }
+
+/// Id of a message as `{chat_id}_{message_id}`, followed by `_{message_thread_id}`
+/// for messages in a forum topic.
+pub fn message_id(message: &telegram_bot_api_rs::available_types::Message) -> String {
+    match message.message_thread_id {
+        Some(thread_id) if message.is_topic_message == Some(true) => {
+            format!("{}_{}_{}", message.chat.id, message.message_id, thread_id)
+        }
+        _ => format!("{}_{}", message.chat.id, message.message_id),
+    }
+}
+
+/// The forum topic of a message id built by `message_id`.
+pub fn message_thread_id(message_id: &str) -> Option<i64> {
+    message_id.split('_').nth(2)?.parse().ok()
+}
+
+/// Split a send target, a chat id optionally followed by `_{message_thread_id}`
+/// to post into a forum topic. `@username` targets are kept whole, usernames may contain `_`.
+pub fn split_target(target: String) -> Result<(String, Option<i64>)> {
+    match target.split_once('_') {
+        Some((chat_id, thread_id)) if chat_id.parse::<i64>().is_ok() => {
+            Ok((chat_id.to_string(), Some(thread_id.parse()?)))
+        }
+        _ => Ok((target, None)),
+    }
+}
+
+pub fn chat_member_user(
+    member: telegram_bot_api_rs::available_types::ChatMember,
+) -> telegram_bot_api_rs::available_types::User {
+    use telegram_bot_api_rs::available_types::ChatMember;
+    match member {
+        ChatMember::Owner { user, .. }
+        | ChatMember::Administrator { user, .. }
+        | ChatMember::Member { user, .. }
+        | ChatMember::Restricted { user, .. }
+        | ChatMember::Left { user }
+        | ChatMember::Banned { user, .. } => user,
+    }
+}
+
+/// Length of `text` in UTF-16 code units, the unit of telegram entity offsets.
+pub fn utf16_len(text: &str) -> i64 {
+    text.encode_utf16().count() as i64
+}
+
+/// Local path of a `File` uri: one without scheme (`/abs/path`) or a `file://localhost/abs/path`.
+/// `None` for http(s) uris, which telegram downloads itself. Any other scheme is an error.
+pub fn local_path(uri: &Uri) -> Result<Option<&str>> {
+    match (uri.scheme_str(), uri.host()) {
+        (None, _) | (Some("file"), None | Some("localhost")) => Ok(Some(uri.path())),
+        (Some("http") | Some("https"), _) => Ok(None),
+        (Some(scheme), _) => Err(anyhow::anyhow!(
+            "Unsupported file uri, expected a local path or http(s) url: {}://",
+            scheme
+        )),
+    }
+}
+
+/// A `File` for a local path or `file://` uri.
+/// `file:///abs/path` isn't a valid `hyper::Uri`, so build such files with this
+/// rather than `File::try_from_path`.
+pub async fn local_file(path: &str) -> Result<File> {
+    let path = match path.strip_prefix("file://") {
+        Some(path) => path.strip_prefix("localhost").unwrap_or(path),
+        None => path,
+    };
+    File::try_from_path(path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_target_keeps_usernames() {
+        assert_eq!(
+            split_target("@my_channel".to_string()).unwrap(),
+            ("@my_channel".to_string(), None)
+        );
+        assert_eq!(
+            split_target("-1001_42".to_string()).unwrap(),
+            ("-1001".to_string(), Some(42))
+        );
+        assert_eq!(
+            split_target("-1001".to_string()).unwrap(),
+            ("-1001".to_string(), None)
+        );
+        assert!(split_target("-1001_x".to_string()).is_err());
+    }
+
+    #[test]
+    fn local_path_accepts_only_local_and_http_uris() {
+        let path = |uri: &str| local_path(&uri.parse().unwrap()).map(|p| p.map(str::to_string));
+        assert_eq!(path("/abs/path").unwrap().as_deref(), Some("/abs/path"));
+        assert_eq!(
+            path("file://localhost/abs/path").unwrap().as_deref(),
+            Some("/abs/path")
+        );
+        assert_eq!(path("https://x.com/a.png").unwrap(), None);
+        assert!(path("file://remote/abs/path").is_err());
+        assert!(path("ftp://x.com/a.png").is_err());
+    }
+
+    #[tokio::test]
+    async fn local_file_accepts_file_uris() {
+        let path = std::env::temp_dir().join("telegram_bot_oxidebot_local_file.txt");
+        tokio::fs::write(&path, b"hi").await.unwrap();
+        let path = path.to_str().unwrap();
+        for uri in [
+            path.to_string(),
+            format!("file://{}", path),
+            format!("file://localhost{}", path),
+        ] {
+            let file = local_file(&uri).await.unwrap();
+            assert_eq!(file.uri.unwrap().path(), path);
+        }
+    }
+}
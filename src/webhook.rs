@@ -0,0 +1,191 @@
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+use anyhow::Result;
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use hyper::{
+    body::{Bytes, Incoming},
+    server::conn::http1,
+    service::service_fn,
+    Method, Request, Response, StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use telegram_bot_api_rs::getting_updates::{
+    payload::{DeleteWebhookPayload, SetWebhookPayload},
+    types::UpdateData,
+};
+use tokio::{
+    net::TcpListener,
+    sync::{broadcast, watch},
+};
+
+use crate::{bot::TelegramBot, event::parse_raw_update};
+
+const SECRET_TOKEN_HEADER: &str = "X-Telegram-Bot-Api-Secret-Token";
+/// Largest accepted request body, updates are far smaller.
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// Receive updates through a webhook instead of long polling.
+/// Telegram posts to `url`, which is expected to reach `listen` (e.g. through a reverse proxy).
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    /// Public https url registered with `setWebhook`.
+    pub url: String,
+    /// Local address the http server binds to.
+    pub listen: SocketAddr,
+    /// Path updates are accepted on, defaults to the path of `url`.
+    pub path: String,
+    /// Checked against the `X-Telegram-Bot-Api-Secret-Token` header of every request.
+    pub secret_token: Option<String>,
+    /// Path of a self-signed public key certificate to upload.
+    pub certificate: Option<String>,
+    pub ip_address: Option<String>,
+    pub max_connections: Option<i64>,
+    pub allowed_updates: Option<Vec<String>>,
+    pub drop_pending_updates: Option<bool>,
+}
+
+impl WebhookConfig {
+    pub fn new(url: String, listen: SocketAddr) -> Self {
+        let path = url
+            .parse::<hyper::Uri>()
+            .map(|uri| uri.path().to_string())
+            .unwrap_or_else(|_| "/".to_string());
+        Self {
+            url,
+            listen,
+            path,
+            secret_token: None,
+            certificate: None,
+            ip_address: None,
+            max_connections: None,
+            allowed_updates: None,
+            drop_pending_updates: None,
+        }
+    }
+}
+
+impl TelegramBot {
+    /// Register the webhook and serve updates until `stop_webhook` is called.
+    pub(crate) async fn run_webhook(&self, config: &WebhookConfig) -> Result<()> {
+        let listener = TcpListener::bind(config.listen).await?;
+        self.bot
+            .set_webhook(SetWebhookPayload {
+                url: config.url.clone(),
+                certificate: config.certificate.clone(),
+                ip_address: config.ip_address.clone(),
+                max_connections: config.max_connections,
+                allowed_updates: config.allowed_updates.clone(),
+                drop_pending_updates: config.drop_pending_updates,
+                secret_token: config.secret_token.clone(),
+            })
+            .await?;
+        tracing::info!("Webhook listening on {}", config.listen);
+        serve_webhook(
+            listener,
+            config.path.clone(),
+            config.secret_token.clone(),
+            self.bot.sender.clone(),
+            self.webhook_shutdown.subscribe(),
+        )
+        .await
+    }
+
+    /// Stop the webhook server and delete the webhook, call this before exiting.
+    pub async fn stop_webhook(&self) -> Result<()> {
+        self.webhook_shutdown.send_replace(true);
+        self.bot
+            .delete_webhook(&DeleteWebhookPayload {
+                drop_pending_updates: None,
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+/// Serve webhook requests on `listener`, forwarding every update to `sender`,
+/// until `shutdown` turns `true`. Does not talk to Telegram, so it can be fed canned updates.
+pub async fn serve_webhook(
+    listener: TcpListener,
+    path: String,
+    secret_token: Option<String>,
+    sender: Arc<broadcast::Sender<UpdateData>>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let path = Arc::new(path);
+    let secret_token = Arc::new(secret_token);
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => accepted?.0,
+            _ = shutdown.wait_for(|stopped| *stopped) => return Ok(()),
+        };
+        let path = path.clone();
+        let secret_token = secret_token.clone();
+        let sender = sender.clone();
+        let service = service_fn(move |req| {
+            handle_request(req, path.clone(), secret_token.clone(), sender.clone())
+        });
+        tokio::spawn(async move {
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::error!("Error while serving webhook connection: {:?}", e);
+            }
+        });
+    }
+}
+
+async fn handle_request(
+    req: Request<Incoming>,
+    path: Arc<String>,
+    secret_token: Arc<Option<String>>,
+    sender: Arc<broadcast::Sender<UpdateData>>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    if req.method() != Method::POST || req.uri().path() != path.as_str() {
+        return Ok(response(StatusCode::NOT_FOUND));
+    }
+    if let Some(secret_token) = secret_token.as_ref() {
+        let header = req.headers().get(SECRET_TOKEN_HEADER);
+        if header.map(|h| h.as_bytes()) != Some(secret_token.as_bytes()) {
+            tracing::warn!("Rejected webhook request with invalid secret token");
+            return Ok(response(StatusCode::UNAUTHORIZED));
+        }
+    }
+    let body = match Limited::new(req.into_body(), MAX_BODY_SIZE).collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(e) if e.is::<LengthLimitError>() => {
+            tracing::warn!(
+                "Rejected webhook request with a body over {} bytes",
+                MAX_BODY_SIZE
+            );
+            return Ok(response(StatusCode::PAYLOAD_TOO_LARGE));
+        }
+        Err(e) => {
+            tracing::error!("Error while reading webhook body: {:?}", e);
+            return Ok(response(StatusCode::BAD_REQUEST));
+        }
+    };
+    let update = match serde_json::from_slice(&body) {
+        Ok(update) => update,
+        Err(e) => {
+            tracing::error!("Error while reading webhook update: {:?}", e);
+            return Ok(response(StatusCode::BAD_REQUEST));
+        }
+    };
+    // Telegram would redeliver an update answered with an error, so unparsable ones are skipped
+    match parse_raw_update(update) {
+        Ok(update) => {
+            if sender.send(update.data).is_err() {
+                tracing::warn!("Dropped webhook update, no subscriber");
+            }
+        }
+        Err(e) => tracing::error!("Error while parsing webhook update: {:?}", e),
+    }
+    Ok(response(StatusCode::OK))
+}
+
+fn response(status: StatusCode) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::new()));
+    *response.status_mut() = status;
+    response
+}
@@ -0,0 +1,87 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use reqwest::StatusCode;
+use telegram_bot_api_rs::getting_updates::types::UpdateData;
+use telegram_bot_oxidebot::webhook::serve_webhook;
+use tokio::{
+    net::TcpListener,
+    sync::{broadcast, watch},
+};
+
+const UPDATE: &str = r#"{
+    "update_id": 1,
+    "message": {
+        "message_id": 5,
+        "date": 0,
+        "chat": { "id": 42, "type": "private" },
+        "text": "hi"
+    }
+}"#;
+
+async fn start() -> (
+    SocketAddr,
+    broadcast::Receiver<UpdateData>,
+    watch::Sender<bool>,
+) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (sender, receiver) = broadcast::channel(8);
+    let (shutdown, shutdown_receiver) = watch::channel(false);
+    tokio::spawn(serve_webhook(
+        listener,
+        "/hook".to_string(),
+        Some("secret".to_string()),
+        Arc::new(sender),
+        shutdown_receiver,
+    ));
+    (addr, receiver, shutdown)
+}
+
+async fn post(
+    addr: SocketAddr,
+    secret: Option<&str>,
+    body: impl Into<reqwest::Body>,
+) -> StatusCode {
+    let mut request = reqwest::Client::new()
+        .post(format!("http://{}/hook", addr))
+        .body(body);
+    if let Some(secret) = secret {
+        request = request.header("X-Telegram-Bot-Api-Secret-Token", secret);
+    }
+    request.send().await.unwrap().status()
+}
+
+#[tokio::test]
+async fn forwards_updates_with_the_secret_token() {
+    let (addr, mut receiver, shutdown) = start().await;
+    assert_eq!(post(addr, Some("secret"), UPDATE).await, StatusCode::OK);
+    assert!(matches!(
+        receiver.try_recv().unwrap(),
+        UpdateData::Message { message } if message.message_id == 5
+    ));
+    shutdown.send_replace(true);
+}
+
+#[tokio::test]
+async fn rejects_wrong_or_missing_secret_token() {
+    let (addr, mut receiver, shutdown) = start().await;
+    assert_eq!(
+        post(addr, Some("wrong"), UPDATE).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(post(addr, None, UPDATE).await, StatusCode::UNAUTHORIZED);
+    assert!(receiver.try_recv().is_err());
+    shutdown.send_replace(true);
+}
+
+#[tokio::test]
+async fn rejects_oversized_bodies() {
+    let (addr, mut receiver, shutdown) = start().await;
+    let body = vec![b' '; 2 * 1024 * 1024];
+    assert_eq!(
+        post(addr, Some("secret"), body).await,
+        StatusCode::PAYLOAD_TOO_LARGE
+    );
+    assert!(receiver.try_recv().is_err());
+    shutdown.send_replace(true);
+}